@@ -0,0 +1,112 @@
+// Lets every setting be overridden for a single run, through command-line flags or PUDDLER_* environment variables
+use std::env;
+use std::process;
+use std::sync::OnceLock;
+use colored::Colorize;
+use crate::settings::is_switch;
+use crate::APPNAME;
+
+
+static ARGUMENTS: OnceLock<Arguments> = OnceLock::new();
+
+
+// Shorter names for the most common flags.
+const ALIASES: [(&str, &str); 3] = [("discord", "discord_presence"), ("transcode", "transcoding"), ("mpv", "mpv_path")];
+
+
+#[derive(Debug, Default)]
+pub struct Arguments {
+    // An alternate settings file.
+    pub config: Option<String>,
+    // Overridden settings and their raw values, environment variables come first, so flags win.
+    pub overrides: Vec<(String, String)>
+}
+
+
+impl Arguments {
+    pub fn is_overridden(&self, key: &str) -> bool {
+        self.overrides.iter().any(|(overridden, _)| overridden == key)
+    }
+}
+
+
+fn setting_name(flag: &str) -> String {
+    match ALIASES.iter().find(|(alias, _)| *alias == flag) {
+        Some((_, key)) => key.to_string(),
+        None => flag.replace('-', "_")
+    }
+}
+
+
+fn print_help() {
+    println!("Usage: {} [--config <path>] [--<setting> <value>] [--<switch>] [--no-<switch>]", APPNAME.to_lowercase());
+    println!();
+    println!("Every setting of {APPNAME}.toml can be overridden for this run, without changing the file:");
+    println!("  --<setting> <value> or --<setting>=<value>   f.e. --progress-interval 5 or --mpv-path=/usr/bin/mpv");
+    println!("  --<switch> / --no-<switch>                   f.e. --transcode, --no-discord or --fullscreen");
+    println!("  PUDDLER_<SETTING>=<value>                    f.e. PUDDLER_FULLSCREEN=0");
+    println!("  --config <path> or PUDDLER_CONFIG=<path>     use another settings file");
+    println!();
+    println!("Dashes in flags stand for underscores in the setting's name. Flags take precedence over environment variables.");
+}
+
+
+fn parse() -> Result<Arguments, String> {
+    let mut arguments = Arguments {
+        config: env::var("PUDDLER_CONFIG").ok(),
+        overrides: Vec::new()
+    };
+    for (name, value) in env::vars() {
+        if let Some(setting) = name.strip_prefix("PUDDLER_").filter(|setting| *setting != "CONFIG") {
+            arguments.overrides.push((setting.to_lowercase(), value));
+        }
+    }
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(format!("Unexpected argument \"{arg}\"."))
+        };
+        let (flag, inline) = match flag.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (flag, None)
+        };
+        match flag {
+            "help" => {
+                print_help();
+                process::exit(0);
+            },
+            "config" => {
+                arguments.config = Some(inline.or_else(|| args.next()).ok_or("--config needs a path.")?);
+                continue
+            },
+            _ => ()
+        }
+        let value = match flag.strip_prefix("no-").map(setting_name) {
+            Some(key) if is_switch(&key) => {
+                arguments.overrides.push((key, "false".to_string()));
+                continue
+            },
+            _ => inline
+        };
+        let key = setting_name(flag);
+        let value = match value {
+            Some(value) => value,
+            None if is_switch(&key) => "true".to_string(),
+            None => args.next().ok_or(format!("--{flag} needs a value."))?
+        };
+        arguments.overrides.push((key, value));
+    }
+    Ok(arguments)
+}
+
+
+// The arguments are only parsed once, errors end puddler before anything else happens.
+pub fn get() -> &'static Arguments {
+    ARGUMENTS.get_or_init(|| match parse() {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            println!("{}\nSee --help for all options.", e.red());
+            process::exit(2);
+        }
+    })
+}
@@ -0,0 +1,282 @@
+// The players puddler can drive: the linked libmpv, an external mpv (controlled through its JSON IPC) and the ones in players.rs
+use std::process::{self, Child, Command};
+use std::sync::Mutex;
+use std::time::Duration;
+use serde_json::json;
+use crate::mpv_ipc::{ipc_path, MpvIpc};
+use crate::players::{CommandPlayer, Vlc};
+use crate::settings::Settings;
+
+
+// Every external player started by puddler, so they can all be closed on Ctrl+C.
+static CHILDREN: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+
+
+#[derive(Debug, Clone, Copy)]
+pub enum PropertyFormat {
+    Double,
+    Flag,
+    Str
+}
+
+
+#[derive(Debug, Clone)]
+pub enum PropertyValue {
+    Double(f64),
+    Flag(bool),
+    Str(String)
+}
+
+
+#[derive(Debug)]
+pub enum PlayerEvent {
+    Shutdown,
+    // Whether the file has been played until the end.
+    EndFile(bool),
+    PlaybackRestart,
+    PropertyChange(String, PropertyValue),
+    // Arguments of a script-message, f.e. from the keybindings.
+    ClientMessage(Vec<String>)
+}
+
+
+// Values are always passed as strings, mpv converts them to the type of the property.
+pub trait Player {
+    fn set_property(&mut self, name: &str, value: &str) -> Result<(), String>;
+    fn command(&mut self, args: &[&str]) -> Result<(), String>;
+    fn observe_property(&mut self, id: u64, name: &str, format: PropertyFormat) -> Result<(), String>;
+    fn wait_event(&mut self, timeout: f64) -> Option<PlayerEvent>;
+
+    // Players, which can't tell their position, only report when they have been closed.
+    fn reports_position(&self) -> bool {
+        true
+    }
+
+    // Whether another item can be loaded, once the first one has ended.
+    fn reusable(&self) -> bool {
+        true
+    }
+}
+
+
+pub struct LibMpv {
+    handle: mpv::MpvHandler
+}
+
+
+impl LibMpv {
+    // libmpv ignores mpv.conf, input.conf and scripts unless it's given a config directory.
+    pub fn new(config_dir: Option<&str>) -> Result<LibMpv, String> {
+        let mut builder: mpv::MpvHandlerBuilder = mpv::MpvHandlerBuilder::new().map_err(|e| e.to_string())?;
+        if let Some(config_dir) = config_dir {
+            builder.set_option("config-dir", config_dir).map_err(|e| e.to_string())?;
+            builder.set_option("config", true).map_err(|e| e.to_string())?;
+        }
+        builder.set_option("osc", true).map_err(|e| e.to_string())?;
+        builder.set_option("input-default-bindings", true).map_err(|e| e.to_string())?;
+        builder.set_option("input-vo-keyboard", true).map_err(|e| e.to_string())?;
+        Ok(LibMpv {
+            handle: builder.build().map_err(|e| e.to_string())?
+        })
+    }
+}
+
+
+impl Player for LibMpv {
+    fn set_property(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.handle.set_property(name, value).map_err(|e| e.to_string())
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<(), String> {
+        self.handle.command(args).map_err(|e| e.to_string())
+    }
+
+    fn observe_property(&mut self, id: u64, name: &str, format: PropertyFormat) -> Result<(), String> {
+        let observed = match format {
+            PropertyFormat::Double => self.handle.observe_property::<f64>(name, id as u32),
+            PropertyFormat::Flag => self.handle.observe_property::<bool>(name, id as u32),
+            PropertyFormat::Str => self.handle.observe_property::<&str>(name, id as u32)
+        };
+        observed.map_err(|e| e.to_string())
+    }
+
+    fn wait_event(&mut self, timeout: f64) -> Option<PlayerEvent> {
+        match self.handle.wait_event(timeout)? {
+            mpv::Event::Shutdown => Some(PlayerEvent::Shutdown),
+            mpv::Event::EndFile(reason) => Some(PlayerEvent::EndFile(matches!(reason, Ok(mpv::EndFileReason::MPV_END_FILE_REASON_EOF)))),
+            mpv::Event::PlaybackRestart => Some(PlayerEvent::PlaybackRestart),
+            mpv::Event::PropertyChange { name, change, .. } => {
+                let value = match change {
+                    mpv::Format::Double(value) => PropertyValue::Double(value),
+                    mpv::Format::Flag(value) => PropertyValue::Flag(value),
+                    mpv::Format::Str(value) => PropertyValue::Str(value.to_string()),
+                    _ => return None
+                };
+                Some(PlayerEvent::PropertyChange(name.to_string(), value))
+            },
+            mpv::Event::ClientMessage(args) => Some(PlayerEvent::ClientMessage(args.iter().map(|arg| arg.to_string()).collect())),
+            _ => None
+        }
+    }
+}
+
+
+pub struct ExternalMpv {
+    pid: u32,
+    ipc: MpvIpc,
+    observed: Vec<(u64, PropertyFormat)>
+}
+
+
+impl ExternalMpv {
+    // mpv closes itself once the loaded file has ended (--idle=once).
+    pub fn spawn(mpv_path: &str, args: &[String]) -> Result<ExternalMpv, String> {
+        let ipc = ipc_path(&format!("puddler-{}", uuid::Uuid::new_v4()));
+        let child = Command::new(mpv_path)
+            .args(["--idle=once".to_string(), format!("--input-ipc-server={}", ipc.display())])
+            .args(args)
+            .spawn()
+            .map_err(|e| format!("Couldn't start mpv (\"{mpv_path}\"): {e}"))?;
+        let pid = register(child);
+        match MpvIpc::connect(&ipc, Duration::from_secs(10)) {
+            Ok(ipc) => Ok(ExternalMpv {
+                pid,
+                ipc,
+                observed: Vec::new()
+            }),
+            Err(e) => {
+                kill(pid);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn has_exited(&self) -> bool {
+        has_exited(self.pid)
+    }
+}
+
+
+impl Player for ExternalMpv {
+    fn set_property(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.ipc.set_property(name, json!(value))
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<(), String> {
+        self.ipc.command(json!(args)).map(|_| ())
+    }
+
+    // Strings are observed as such, otherwise f.e. "aid" would be reported as a number or false.
+    fn observe_property(&mut self, id: u64, name: &str, format: PropertyFormat) -> Result<(), String> {
+        let command = match format {
+            PropertyFormat::Str => "observe_property_string",
+            _ => "observe_property"
+        };
+        self.ipc.command(json!([command, id, name]))?;
+        self.observed.push((id, format));
+        Ok(())
+    }
+
+    fn wait_event(&mut self, timeout: f64) -> Option<PlayerEvent> {
+        let event = match self.ipc.next_event(Duration::from_secs_f64(timeout)) {
+            Ok(event) => event?,
+            // The connection is gone once mpv has been closed.
+            Err(_) => return Some(PlayerEvent::Shutdown)
+        };
+        match event["event"].as_str()? {
+            "shutdown" => Some(PlayerEvent::Shutdown),
+            "end-file" => Some(PlayerEvent::EndFile(event["reason"].as_str() == Some("eof"))),
+            "playback-restart" => Some(PlayerEvent::PlaybackRestart),
+            "property-change" => {
+                let id = event["id"].as_u64()?;
+                let (_, format) = self.observed.iter().find(|(observed, _)| *observed == id)?;
+                let value = match format {
+                    PropertyFormat::Double => PropertyValue::Double(event["data"].as_f64()?),
+                    PropertyFormat::Flag => PropertyValue::Flag(event["data"].as_bool()?),
+                    PropertyFormat::Str => PropertyValue::Str(event["data"].as_str()?.to_string())
+                };
+                Some(PlayerEvent::PropertyChange(event["name"].as_str()?.to_string(), value))
+            },
+            "client-message" => Some(PlayerEvent::ClientMessage(event["args"].as_array()?.iter().filter_map(|arg| arg.as_str().map(str::to_string)).collect())),
+            _ => None
+        }
+    }
+}
+
+
+impl Drop for ExternalMpv {
+    fn drop(&mut self) {
+        kill(self.pid);
+    }
+}
+
+
+pub fn mpv_path(settings: &Settings) -> String {
+    settings.mpv_path.clone().unwrap_or("mpv".to_string())
+}
+
+
+// Arguments every external mpv is started with.
+pub fn mpv_args(settings: &Settings) -> Vec<String> {
+    settings.mpv_config_dir.iter().map(|config_dir| format!("--config-dir={config_dir}")).collect()
+}
+
+
+pub fn start(settings: &Settings) -> Result<Box<dyn Player>, String> {
+    match settings.player_backend.as_str() {
+        "external" => Ok(Box::new(ExternalMpv::spawn(&mpv_path(settings), &[mpv_args(settings), ["--force-window=immediate".to_string()].to_vec()].concat())?)),
+        "vlc" => Ok(Box::new(Vlc::new(settings.vlc_path.clone().unwrap_or("vlc".to_string())))),
+        "command" => match &settings.player_command {
+            Some(template) => Ok(Box::new(CommandPlayer::new(template.clone()))),
+            None => Err("There is no player command configured.".to_string())
+        },
+        _ => Ok(Box::new(LibMpv::new(settings.mpv_config_dir.as_deref())?))
+    }
+}
+
+
+pub fn register(child: Child) -> u32 {
+    let pid = child.id();
+    CHILDREN.lock().unwrap().push(child);
+    pid
+}
+
+
+pub fn has_exited(pid: u32) -> bool {
+    let mut children = CHILDREN.lock().unwrap();
+    let Some(position) = children.iter().position(|child| child.id() == pid) else { return true };
+    match children[position].try_wait() {
+        Ok(None) => false,
+        _ => {
+            children.remove(position).wait().ok();
+            true
+        }
+    }
+}
+
+
+pub fn kill(pid: u32) {
+    let mut children = CHILDREN.lock().unwrap();
+    if let Some(position) = children.iter().position(|child| child.id() == pid) {
+        let mut child = children.remove(position);
+        child.kill().ok();
+        child.wait().ok();
+    }
+}
+
+
+pub fn kill_all() {
+    for mut child in CHILDREN.lock().unwrap().drain(..) {
+        child.kill().ok();
+        child.wait().ok();
+    }
+}
+
+
+// Without this, Ctrl+C would leave the external players behind.
+pub fn handle_interrupt() {
+    ctrlc::set_handler(|| {
+        kill_all();
+        process::exit(130);
+    }).ok();
+}
@@ -0,0 +1,111 @@
+// Measures the connection speed to the media-server, the same way the official clients do it
+use std::io;
+use std::io::prelude::*;
+use std::time::{Duration, Instant, SystemTime};
+use colored::Colorize;
+use isahc::Request;
+use isahc::prelude::*;
+use crate::config::{read_bitrate_test, write_bitrate_test};
+use crate::mediaserver_information::{BitrateTest, HeadDict};
+use crate::is_numeric;
+
+// A measured bitrate is reused for one day before it's tested again.
+const BITRATE_TEST_LIFETIME: u64 = 60 * 60 * 24;
+
+// (download size in bytes, bitrate under which the result is good enough)
+const BITRATE_TESTS: [(u64, u64); 3] = [
+    (500000, 500000),
+    (1000000, 20000000),
+    (3000000, 50000000),
+];
+
+
+pub fn get_bitrate(head_dict: &HeadDict) -> u64 {
+    if let Some(cached) = read_bitrate_test(&head_dict.config_path) {
+        if now() < cached.timestamp + BITRATE_TEST_LIFETIME {
+            println!("Using your last measured connection speed: {}", format_bitrate(cached.bitrate).green());
+            return cached.bitrate;
+        }
+    }
+    remeasure_bitrate(head_dict)
+}
+
+
+pub fn remeasure_bitrate(head_dict: &HeadDict) -> u64 {
+    println!("Measuring your connection speed to {} ...", head_dict.media_server_name);
+    match detect_bitrate(head_dict) {
+        Some(bitrate) => {
+            println!("Measured connection speed: {}", format_bitrate(bitrate).green());
+            write_bitrate_test(&head_dict.config_path, BitrateTest {
+                bitrate,
+                timestamp: now()
+            });
+            bitrate
+        },
+        None => {
+            println!("{}", "Couldn't measure your connection speed.".to_string().red());
+            ask_bitrate()
+        }
+    }
+}
+
+
+fn detect_bitrate(head_dict: &HeadDict) -> Option<u64> {
+    let mut bitrate: u64 = 0;
+    for (size, threshold) in BITRATE_TESTS {
+        bitrate = download_speed(head_dict, size)?;
+        if bitrate < threshold {
+            break
+        }
+    }
+    // Leave some headroom, since the test doesn't account for the overhead of the actual stream.
+    Some((bitrate as f64 * 0.7).round() as u64)
+}
+
+
+fn download_speed(head_dict: &HeadDict, size: u64) -> Option<u64> {
+    let request_header = &head_dict.request_header;
+    let start = Instant::now();
+    let mut response = Request::get(format!("{}{}/Playback/BitrateTest?Size={}", head_dict.config_file.ipaddress, head_dict.media_server, size))
+        .timeout(Duration::from_secs(30))
+        .header("X-Application", &request_header.application)
+        .header("X-Emby-Token", &request_header.token)
+        .body(()).ok()?
+        .send().ok()?;
+    if !response.status().is_success() {
+        return None
+    }
+    let received = response.copy_to(io::sink()).ok()?;
+    let seconds = start.elapsed().as_secs_f64();
+    if received == 0 || seconds == 0.0 {
+        return None
+    }
+    Some((received as f64 * 8.0 / seconds).round() as u64)
+}
+
+
+fn ask_bitrate() -> u64 {
+    print!("\nPlease enter your connection speed in mbps: ");
+    let mut mbps: String = String::new();
+    loop {
+        io::stdout().flush().expect("Failed to flush stdout");
+        mbps.clear();
+        io::stdin().read_line(&mut mbps).unwrap();
+        if ! is_numeric(mbps.trim()) || mbps.trim().parse::<u64>().is_err() {
+            print!("\nInvalid input! Enter something like \"25\" equal to ~3MB/s.\n: ")
+        } else {
+            break
+        }
+    };
+    mbps.trim().parse::<u64>().unwrap() * 1000000
+}
+
+
+fn format_bitrate(bitrate: u64) -> String {
+    format!("{:.1} Mbps", bitrate as f64 / 1000000.0)
+}
+
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
@@ -0,0 +1,155 @@
+// Plays episode after episode in the same mpv window, with a cancellable countdown in between
+use std::io;
+use std::io::prelude::*;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use colored::Colorize;
+use crate::backend::{Player, PlayerEvent};
+use crate::mediaserver_information::{key_within, HeadDict};
+use crate::player::{play_in, PlaybackEnd};
+use crate::settings::Settings;
+use crate::Items;
+
+
+// When the user last did something, f.e. pausing or answering a prompt.
+static LAST_INTERACTION: Mutex<Option<Instant>> = Mutex::new(None);
+
+
+// Only enabled while waiting between episodes, so mpv's own bindings stay untouched during playback.
+const SECTION: &str = "ENTER script-message puddler continue\nESC script-message puddler cancel\n";
+
+
+pub fn touch() {
+    *LAST_INTERACTION.lock().unwrap() = Some(Instant::now());
+}
+
+
+fn last_interaction() -> Instant {
+    LAST_INTERACTION.lock().unwrap().unwrap_or_else(Instant::now)
+}
+
+
+// Asks in the terminal and in mpv at the same time. With a countdown, no answer means yes, otherwise it waits for one.
+fn ask(mut mpv: Option<&mut Box<dyn Player>>, question: &str, countdown: Option<u64>) -> bool {
+    if let Some(mpv) = mpv.as_mut() {
+        if mpv.command(&["define-section", "puddler-binge", SECTION, "force"]).is_ok() {
+            mpv.command(&["enable-section", "puddler-binge"]).ok();
+        }
+    }
+    let started = Instant::now();
+    let mut shown: Option<u64> = None;
+    let answer: Option<bool> = loop {
+        let elapsed: u64 = started.elapsed().as_secs();
+        let remaining: Option<u64> = countdown.map(|seconds| seconds.saturating_sub(elapsed));
+        if remaining == Some(0) {
+            break None
+        }
+        if shown != Some(elapsed) {
+            shown = Some(elapsed);
+            let text: String = match remaining {
+                Some(remaining) => format!("{question} in {remaining}s"),
+                None => question.to_string()
+            };
+            print!("\r{} [ENTER] Continue | Any other key stops ", text.cyan());
+            io::stdout().flush().expect("Failed to flush stdout");
+            if let Some(mpv) = mpv.as_mut() {
+                mpv.command(&["show-text", &format!("{text}\n[ENTER] Continue | [ESC] Stop"), "1500"]).ok();
+            }
+        }
+        match key_within(Duration::from_millis(200)) {
+            Some('\n') | Some('\r') => break Some(true),
+            Some(_) => break Some(false),
+            None => ()
+        }
+        if let Some(mpv) = mpv.as_mut() {
+            match mpv.wait_event(0.05) {
+                Some(PlayerEvent::ClientMessage(args)) if args.first().is_some_and(|target| target == "puddler") => {
+                    match args.get(1).map(|message| message.as_str()) {
+                        Some("continue") | Some("next") => break Some(true),
+                        Some("cancel") => break Some(false),
+                        _ => ()
+                    }
+                },
+                // The window has been closed.
+                Some(PlayerEvent::Shutdown) => break Some(false),
+                _ => ()
+            }
+        }
+    };
+    println!("\n");
+    if let Some(mpv) = mpv.as_mut() {
+        mpv.command(&["disable-section", "puddler-binge"]).ok();
+        mpv.command(&["show-text", "", "1"]).ok();
+    }
+    if answer.is_some() {
+        touch();
+    }
+    answer.unwrap_or(true)
+}
+
+
+// Skips items, which have already been played, going back first if that's where the user wanted to go.
+fn unplayed(queue: &[Items], position: usize, backwards: bool, played: Option<&Mutex<Vec<String>>>) -> Option<usize> {
+    let Some(played) = played else {
+        return Some(position).filter(|position| *position < queue.len())
+    };
+    let played = played.lock().unwrap();
+    let is_unplayed = |index: &usize| !played.contains(&queue[*index].Id);
+    let earlier = if backwards { (0..=position.min(queue.len().saturating_sub(1))).rev().find(is_unplayed) } else { None };
+    earlier.or_else(|| (position..queue.len()).find(is_unplayed))
+}
+
+
+// Returns the position to continue at, once binging has been stopped.
+// With a list of played ids (f.e. while shuffling), every item is added once it starts and never played again.
+pub fn binge(settings: &Settings, head_dict: &HeadDict, queue: &[Items], position: usize, played: Option<&Mutex<Vec<String>>>) -> usize {
+    let mut player: Option<Box<dyn Player>> = None;
+    // Episodes, which have been watched without any interaction.
+    let mut episodes: u64 = 0;
+    let Some(mut position) = unplayed(queue, position, false, played) else {
+        println!("\nYou've reached the end of your episode list.");
+        return queue.len()
+    };
+    touch();
+    loop {
+        let started = Instant::now();
+        if let Some(played) = played {
+            played.lock().unwrap().push(queue[position].Id.clone());
+        }
+        let end: PlaybackEnd = play_in(settings, head_dict, &queue[position], queue, &mut player);
+        let next: Option<usize> = match end {
+            PlaybackEnd::Previous => unplayed(queue, position.saturating_sub(1), true, played),
+            _ => unplayed(queue, position + 1, false, played)
+        };
+        if matches!(end, PlaybackEnd::Closed) {
+            println!("The player has been closed, binge mode stopped.");
+            return next.unwrap_or(queue.len())
+        }
+        let Some(next) = next else {
+            println!("\nYou've reached the end of your episode list.");
+            return queue.len()
+        };
+        position = next;
+        episodes = if last_interaction() > started { 0 } else { episodes + 1 };
+        let inactive: Duration = last_interaction().elapsed();
+        let next: &Items = &queue[position];
+        let title: String = match &next.SeriesName {
+            Some(series) => format!("{series} - {}", next.Name),
+            None => next.Name.clone()
+        };
+        let answer: bool = if (settings.binge_episodes > 0 && episodes >= settings.binge_episodes)
+            || (settings.binge_hours > 0 && inactive >= Duration::from_secs(settings.binge_hours * 3600)) {
+            episodes = 0;
+            ask(player.as_mut(), &format!("Are you still watching? Next: {title}"), None)
+        } else if matches!(end, PlaybackEnd::Next | PlaybackEnd::Previous) {
+            // Skipping to another episode is already an answer.
+            true
+        } else {
+            ask(player.as_mut(), &format!("Next: {title}"), Some(settings.binge_countdown))
+        };
+        if !answer {
+            println!("Binge mode stopped.");
+            return position
+        }
+    }
+}
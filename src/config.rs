@@ -1,4 +1,5 @@
 // This part of puddler parses and writes the emby and jellyfin config files
+use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
@@ -10,6 +11,8 @@ use crate::APP_INFO;
 use crate::mediaserver_information::ConfigFileRaw;
 use crate::mediaserver_information::ConfigFile;
 use crate::mediaserver_information::ConfigFileUser;
+use crate::mediaserver_information::BitrateTest;
+use crate::settings::SettingsOverrides;
 use crate::mediaserver_information::getch;
 use crate::is_numeric;
 
@@ -73,7 +76,11 @@ pub fn choose_config(server_kind: char, autologin: bool) -> Option<String> {
 }
 
 
-pub fn read_config(config_path_string: &String, autologin: bool) -> Result<(ConfigFile, ConfigFileRaw), (Option<ConfigFileRaw>, &str)> {
+// The raw config is passed along, if a user should be added to it.
+pub type ConfigError<'a> = (Option<Box<ConfigFileRaw>>, &'a str);
+
+
+pub fn read_config(config_path_string: &String, autologin: bool) -> Result<(ConfigFile, ConfigFileRaw), ConfigError<'_>> {
     let file = std::fs::read_to_string(config_path_string).unwrap();
     let local_config_file: Result<ConfigFileRaw, serde_json::Error> = serde_json::from_str::<ConfigFileRaw>(&file);
     match local_config_file {
@@ -124,7 +131,7 @@ pub fn read_config(config_path_string: &String, autologin: bool) -> Result<(Conf
                     match hngfje {
                         '1' => {
                             if a.user.len() == 1 {
-                                return Err((Some(a), "add user"))
+                                return Err((Some(Box::new(a)), "add user"))
                             }
                             println!("Please choose which user you want to switch to.\n(\"Add\" if you want to add a new user)");
                             for (index, thing) in a.user.iter().enumerate() {
@@ -139,7 +146,7 @@ pub fn read_config(config_path_string: &String, autologin: bool) -> Result<(Conf
                                 index_raw.trim().parse::<String>().unwrap();
                                 if ! is_numeric(&index_raw) {
                                     if index_raw.trim() == "Add" {
-                                        return Err((Some(a), "add user"))
+                                        return Err((Some(Box::new(a)), "add user"))
                                     } else {
                                         print!("Invalid input, please try again.\n: ");
                                         io::stdout().flush().expect("Failed to flush stdout");
@@ -186,6 +193,11 @@ pub fn write_config(config_path_string: String, config_file: &ConfigFile, other_
         access_token: config_file.access_token.clone(),
         username: config_file.username.clone()
     };
+    // Values that aren't part of the login (like the cached bitrate) are kept from the existing file.
+    let existing: Option<ConfigFileRaw> = read_raw_config(&config_path_string);
+    let bitrate_test: Option<BitrateTest> = existing.as_ref().and_then(|raw| raw.bitrate_test.clone());
+    let overrides: SettingsOverrides = existing.as_ref().map(|raw| raw.overrides.clone()).unwrap_or_default();
+    let start_offsets: HashMap<String, i32> = existing.map(|raw| raw.start_offsets).unwrap_or_default();
     let config_file_raw = if let Some(mut other_users) = other_users {
         let mut user_vec: Vec<ConfigFileUser> = [config_file_user].to_vec();
         user_vec.append(&mut other_users);
@@ -193,14 +205,20 @@ pub fn write_config(config_path_string: String, config_file: &ConfigFile, other_
             emby: config_file.emby,
             ipaddress: config_file.ipaddress.clone(),
             device_id: config_file.device_id.clone(),
-            user: user_vec
+            user: user_vec,
+            bitrate_test,
+            overrides,
+            start_offsets
         }
     } else {
         ConfigFileRaw {
             emby: config_file.emby,
             ipaddress: config_file.ipaddress.clone(),
             device_id: config_file.device_id.clone(),
-            user: [config_file_user].to_vec()
+            user: [config_file_user].to_vec(),
+            bitrate_test,
+            overrides,
+            start_offsets
         }
     };
     let result = std::fs::write(config_path_string, serde_json::to_string_pretty(&config_file_raw).unwrap());
@@ -220,3 +238,54 @@ pub fn generate_config_path(server_kind: char, server_id: String, server_name: S
     let app_root = get_app_root(AppDataType::UserConfig, &APP_INFO).unwrap();
     format!("{}/{}/{}.{}.config.json", app_root.display(), folder_suffix, server_name, server_id)
 }
+
+
+fn read_raw_config(config_path_string: &String) -> Option<ConfigFileRaw> {
+    let file = std::fs::read_to_string(config_path_string).ok()?;
+    serde_json::from_str::<ConfigFileRaw>(&file).ok()
+}
+
+
+pub fn read_bitrate_test(config_path_string: &String) -> Option<BitrateTest> {
+    read_raw_config(config_path_string)?.bitrate_test
+}
+
+
+pub fn write_bitrate_test(config_path_string: &String, bitrate_test: BitrateTest) {
+    if let Some(mut config_file_raw) = read_raw_config(config_path_string) {
+        config_file_raw.bitrate_test = Some(bitrate_test);
+        if std::fs::write(config_path_string, serde_json::to_string_pretty(&config_file_raw).unwrap()).is_err() {
+            println!("{}", "Couldn't save the measured bitrate to the config file.".to_string().red());
+        }
+    }
+}
+
+
+pub fn read_overrides(config_path_string: &String) -> SettingsOverrides {
+    read_raw_config(config_path_string).map(|raw| raw.overrides).unwrap_or_default()
+}
+
+
+pub fn write_overrides(config_path_string: &String, overrides: SettingsOverrides) {
+    if let Some(mut config_file_raw) = read_raw_config(config_path_string) {
+        config_file_raw.overrides = overrides;
+        if std::fs::write(config_path_string, serde_json::to_string_pretty(&config_file_raw).unwrap()).is_err() {
+            println!("{}", "Couldn't save the server settings to the config file.".to_string().red());
+        }
+    }
+}
+
+
+pub fn read_start_offset(config_path_string: &String, series_id: &str) -> Option<i32> {
+    read_raw_config(config_path_string)?.start_offsets.get(series_id).copied()
+}
+
+
+pub fn write_start_offset(config_path_string: &String, series_id: &str, seconds: i32) {
+    if let Some(mut config_file_raw) = read_raw_config(config_path_string) {
+        config_file_raw.start_offsets.insert(series_id.to_string(), seconds);
+        if std::fs::write(config_path_string, serde_json::to_string_pretty(&config_file_raw).unwrap()).is_err() {
+            println!("{}", "Couldn't save the start offset to the config file.".to_string().red());
+        }
+    }
+}
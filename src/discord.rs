@@ -1,6 +1,5 @@
 use discord_rich_presence::{DiscordIpc, activity};
-use crate::mediaserver_information;
-use mediaserver_information::HeadDict;
+use crate::presence::{Presence, PresenceBackend};
 
 
 pub struct DiscordClient {
@@ -9,18 +8,6 @@ pub struct DiscordClient {
 }
 
 
-pub fn mpv_link(use_discord: bool) -> DiscordClient {
-    if use_discord {
-        DiscordClient::default()
-    } else {
-        DiscordClient {
-            client: DiscordClient::default().client,
-            connection: false
-        }
-    }
-}
-
-
 impl Default for DiscordClient {
     fn default() -> Self {
         let mut client = discord_rich_presence::DiscordIpcClient::new("980093587314343957").unwrap();
@@ -33,57 +20,51 @@ impl Default for DiscordClient {
 }
 
 
-impl DiscordClient {
-    pub fn update_presence(&mut self, head_dict: &HeadDict, details: String, state: String, time_left: f64) {
+impl PresenceBackend for DiscordClient {
+    fn update(&mut self, presence: &Presence) {
         if !self.connection {
             self.connection = self.client.connect().is_ok();
         }
-        if self.connection {
-            if details == *"" {
-                self.client.set_activity(activity::Activity::new()
-                                       .assets(activity::Assets::new().small_image(&head_dict.media_server_name.to_lowercase()))
-                                       .timestamps(activity::Timestamps::new().end(time_left.round() as i64))
-                                       .state(&state)
-                                    ).ok();
-            } else {
-                self.client.set_activity(activity::Activity::new()
-                                       .assets(activity::Assets::new().small_image(&head_dict.media_server_name.to_lowercase()))
-                                       .timestamps(activity::Timestamps::new().end(time_left.round() as i64))
-                                       .details(&details)
-                                       .state(&state)
-                                    ).ok();
+        if !self.connection {
+            return
+        }
+        let server_logo = presence.server.to_lowercase();
+        // The poster replaces the server logo, which then moves into the small image.
+        let large_image: &str = presence.artwork.as_deref().unwrap_or(&server_logo);
+        let small_image: &str = if presence.paused {
+            "pause2"
+        } else {
+            &server_logo
+        };
+        let mut timestamps = activity::Timestamps::new();
+        if !presence.paused {
+            if let Some(start) = presence.start {
+                timestamps = timestamps.start(start);
+            }
+            if let Some(end) = presence.end {
+                timestamps = timestamps.end(end);
             }
         }
-    }
-    pub fn pause(&mut self, head_dict: &HeadDict, details: String, state: String) {
-        if !self.connection {
-            self.connection = self.client.connect().is_ok();
+        let state: String = presence.state();
+        let mut activity = activity::Activity::new()
+            .assets(activity::Assets::new()
+                .large_image(large_image)
+                .large_text(&presence.title)
+                .small_image(small_image))
+            .timestamps(timestamps)
+            .state(&state);
+        if let Some(details) = &presence.details {
+            activity = activity.details(details);
         }
+        self.client.set_activity(activity).ok();
+    }
+
+    // Called once an item has ended, every playback connects again, so the connection is closed here.
+    fn clear(&mut self) {
         if self.connection {
-            if details == *"" {
-                self.client
-                    .set_activity(
-                        activity::Activity::new()
-                            .assets(
-                                activity::Assets::new()
-                                    .large_image(&head_dict.media_server_name.to_lowercase())
-                                    .small_image("pause2")
-                                )
-                            .details(&state)
-                    ).ok();
-            } else {
-                self.client
-                    .set_activity(
-                        activity::Activity::new()
-                            .assets(
-                                activity::Assets::new()
-                                    .large_image(&head_dict.media_server_name.to_lowercase())
-                                    .small_image("pause2")
-                                )
-                            .details(&details)
-                            .state(&state)
-                    ).ok();
-            }
+            self.client.clear_activity().ok();
+            self.client.close().ok();
+            self.connection = false;
         }
     }
 }
@@ -0,0 +1,469 @@
+// Keeps a local library of downloaded items, so they can be watched without a connection to the server
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use app_dirs::*;
+use colored::Colorize;
+use http::StatusCode;
+use isahc::config::RedirectPolicy;
+use isahc::prelude::*;
+use isahc::Request;
+use serde_derive::{Deserialize, Serialize};
+use crate::mediaserver_information::{getch, HeadDict};
+use crate::settings::Settings;
+use crate::{puddler_get, ItemJson, Items, APP_INFO};
+
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DownloadedItem {
+    pub item: Items,
+    pub file: String,
+    pub config_path: String,
+    pub bitrate: Option<u64>,
+    // Downloaded because of a download rule, so the rule may delete it again.
+    #[serde(default)]
+    pub by_rule: bool
+}
+
+
+// Unfinished downloads are kept in the queue (and their ".part" file on disk) until they're complete.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QueuedDownload {
+    pub item: Items,
+    pub url: String,
+    pub file: String,
+    pub config_path: String,
+    pub bitrate: Option<u64>,
+    #[serde(default)]
+    pub by_rule: bool
+}
+
+
+// Either the next unwatched or the latest unwatched episodes of a series are kept on disk.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DownloadRule {
+    pub series_id: String,
+    pub series_name: String,
+    pub config_path: String,
+    pub latest: bool,
+    pub count: usize,
+    pub bitrate: Option<u64>
+}
+
+
+struct Progress {
+    name: String,
+    received: u64,
+    total: Option<u64>
+}
+
+
+pub fn library_path(settings: &Settings) -> PathBuf {
+    match &settings.download_folder {
+        Some(folder) => PathBuf::from(folder),
+        None => get_app_root(AppDataType::UserData, &APP_INFO).unwrap().join("downloads")
+    }
+}
+
+
+fn read_json<T: serde::de::DeserializeOwned>(file: PathBuf) -> Vec<T> {
+    match fs::read_to_string(file) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+        Err(_) => Vec::new()
+    }
+}
+
+
+fn write_json<T: serde::Serialize>(file: PathBuf, content: &Vec<T>) {
+    if let Some(folder) = file.parent() {
+        fs::create_dir_all(folder).ok();
+    }
+    if fs::write(&file, serde_json::to_string_pretty(content).unwrap()).is_err() {
+        println!("{}", format!("Couldn't save \"{}\". Check your permissions!", file.display()).red());
+    }
+}
+
+
+pub fn read_library(settings: &Settings) -> Vec<DownloadedItem> {
+    read_json(library_path(settings).join("library.json"))
+}
+
+
+fn write_library(settings: &Settings, library: &Vec<DownloadedItem>) {
+    write_json(library_path(settings).join("library.json"), library)
+}
+
+
+fn read_queue(settings: &Settings) -> Vec<QueuedDownload> {
+    read_json(library_path(settings).join("queue.json"))
+}
+
+
+fn write_queue(settings: &Settings, queue: &Vec<QueuedDownload>) {
+    write_json(library_path(settings).join("queue.json"), queue)
+}
+
+
+fn read_rules(settings: &Settings) -> Vec<DownloadRule> {
+    read_json(library_path(settings).join("rules.json"))
+}
+
+
+fn write_rules(settings: &Settings, rules: &Vec<DownloadRule>) {
+    write_json(library_path(settings).join("rules.json"), rules)
+}
+
+
+// Files that went missing (deleted by hand) are treated as not downloaded.
+pub fn find_download(settings: &Settings, item_id: &str) -> Option<DownloadedItem> {
+    read_library(settings).into_iter()
+        .find(|downloaded| downloaded.item.Id == item_id && Path::new(&downloaded.file).is_file())
+}
+
+
+fn ask_version(name: &str) -> Option<u64> {
+    print!("Which version of {} do you want to download?\n  (O)riginal file | (T)ranscoded version", name.cyan());
+    match getch("OoTt") {
+        'T' | 't' => Some(ask_download_bitrate()),
+        _ => None
+    }
+}
+
+
+pub fn download_item(settings: &Settings, head_dict: &HeadDict, item: &Items) {
+    let bitrate = ask_version(&item.Name);
+    if enqueue(settings, head_dict, item, bitrate, false) {
+        run_queue(settings, head_dict);
+    } else {
+        println!("{} has already been downloaded or is queued.", item.Name.cyan());
+    }
+}
+
+
+// Returns false, if the item is already downloaded or queued.
+fn enqueue(settings: &Settings, head_dict: &HeadDict, item: &Items, bitrate: Option<u64>, by_rule: bool) -> bool {
+    let mut queue = read_queue(settings);
+    if find_download(settings, &item.Id).is_some() || queue.iter().any(|queued| queued.item.Id == item.Id) {
+        return false
+    }
+    let (url, extension) = match bitrate {
+        Some(bitrate) => {
+            (format!("{}{}/Videos/{}/stream.mkv?VideoCodec=hevc,h264&AudioCodec=aac&VideoBitrate={}&AudioBitrate=192000&DeviceId={}",
+                head_dict.config_file.ipaddress, head_dict.media_server, item.Id, bitrate, head_dict.config_file.device_id), "mkv".to_string())
+        },
+        None => {
+            let extension = item.MediaSources.as_ref()
+                .and_then(|sources| sources.first())
+                .and_then(|source| source.Container.clone())
+                .and_then(|container| container.split(',').next().map(|c| c.to_string()))
+                .unwrap_or("mkv".to_string());
+            (format!("{}{}/Items/{}/Download", head_dict.config_file.ipaddress, head_dict.media_server, item.Id), extension)
+        }
+    };
+    queue.push(QueuedDownload {
+        item: item.clone(),
+        url,
+        file: library_path(settings).join(format!("{}.{}", item.Id, extension)).display().to_string(),
+        config_path: head_dict.config_path.clone(),
+        bitrate,
+        by_rule
+    });
+    write_queue(settings, &queue);
+    true
+}
+
+
+pub fn resume_queue(settings: &Settings, head_dict: &HeadDict) {
+    let unfinished = read_queue(settings).iter().filter(|queued| queued.config_path == head_dict.config_path).count();
+    if unfinished == 0 {
+        return
+    }
+    print!("You have {unfinished} unfinished download(s). Do you want to continue them now?\n (Y)es / (N)o");
+    if "Yy".contains(getch("YyNn")) {
+        run_queue(settings, head_dict);
+    }
+}
+
+
+pub fn run_queue(settings: &Settings, head_dict: &HeadDict) {
+    let pending: VecDeque<QueuedDownload> = read_queue(settings).into_iter()
+        .filter(|queued| queued.config_path == head_dict.config_path)
+        .collect();
+    if pending.is_empty() {
+        return
+    }
+    if fs::create_dir_all(library_path(settings)).is_err() {
+        println!("{}", "Couldn't create the download folder. Check your permissions!".to_string().red());
+        return
+    }
+    let workers: usize = (settings.download_concurrency.max(1) as usize).min(pending.len());
+    // The bandwidth cap is shared equally between all running downloads.
+    let cap: Option<u64> = settings.download_bandwidth.map(|mbps| mbps * 1000000 / 8 / workers as u64);
+    println!("Downloading {} item(s), {} at a time ...", pending.len(), workers);
+
+    let pending: Mutex<VecDeque<QueuedDownload>> = Mutex::new(pending);
+    let progress: Mutex<Vec<Option<Progress>>> = Mutex::new((0..workers).map(|_| None).collect());
+    let messages: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    // Guards the library and queue files, since every worker updates them.
+    let files: Mutex<()> = Mutex::new(());
+    thread::scope(|scope| {
+        let handles: Vec<thread::ScopedJoinHandle<()>> = (0..workers).map(|slot| {
+            let (pending, progress, messages, files) = (&pending, &progress, &messages, &files);
+            scope.spawn(move || {
+                loop {
+                    let next = pending.lock().unwrap().pop_front();
+                    let Some(download) = next else { break };
+                    progress.lock().unwrap()[slot] = Some(Progress {
+                        name: download.item.Name.clone(),
+                        received: 0,
+                        total: None
+                    });
+                    let part = PathBuf::from(format!("{}.part", download.file));
+                    let result = fetch_to_file(head_dict, &download.url, &part, cap, |received, total| {
+                        if let Some(current) = progress.lock().unwrap()[slot].as_mut() {
+                            current.received = received;
+                            current.total = total;
+                        }
+                    }).and_then(|()| fs::rename(&part, &download.file).map_err(|e| e.to_string()));
+                    let _guard = files.lock().unwrap();
+                    match result {
+                        Ok(()) => {
+                            let mut library = read_library(settings);
+                            library.retain(|downloaded| downloaded.item.Id != download.item.Id);
+                            library.push(DownloadedItem {
+                                item: download.item.clone(),
+                                file: download.file.clone(),
+                                config_path: download.config_path.clone(),
+                                bitrate: download.bitrate,
+                                by_rule: download.by_rule
+                            });
+                            write_library(settings, &library);
+                            let mut queue = read_queue(settings);
+                            queue.retain(|queued| queued.item.Id != download.item.Id);
+                            write_queue(settings, &queue);
+                            messages.lock().unwrap().push(format!("Downloaded {} to \"{}\".", download.item.Name, download.file).green().to_string());
+                        },
+                        Err(e) => {
+                            messages.lock().unwrap().push(format!("{} {}\n  Error: {}", "Download failed:".to_string().red(), download.item.Name, e));
+                        }
+                    }
+                }
+                progress.lock().unwrap()[slot] = None;
+            })
+        }).collect();
+        // A worker, which panicked, is finished as well.
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            let status: Vec<String> = progress.lock().unwrap_or_else(PoisonError::into_inner).iter().flatten().map(|current| {
+                let name: String = current.name.chars().take(20).collect();
+                match current.total {
+                    Some(total) if total > 0 => format!("{} {:.0}%", name, current.received as f64 / total as f64 * 100.0),
+                    _ => format!("{} {:.1} MB", name, current.received as f64 / 1000000.0)
+                }
+            }).collect();
+            print!("\r  {:<100}", status.join(" | "));
+            io::stdout().flush().expect("Failed to flush stdout");
+            thread::sleep(Duration::from_millis(500));
+        }
+        for handle in handles {
+            if handle.join().is_err() {
+                messages.lock().unwrap_or_else(PoisonError::into_inner).push("A download worker crashed, its downloads stay queued.".to_string().red().to_string());
+            }
+        }
+    });
+    println!();
+    for message in messages.into_inner().unwrap_or_else(PoisonError::into_inner) {
+        println!("{message}");
+    }
+}
+
+
+// Continues a partial download with a range request, if the server supports it.
+fn fetch_to_file(head_dict: &HeadDict, url: &str, file: &Path, cap: Option<u64>, mut report: impl FnMut(u64, Option<u64>)) -> Result<(), String> {
+    let existing: u64 = fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0);
+    let mut request = Request::get(url)
+        .connect_timeout(Duration::from_secs(5))
+        .redirect_policy(RedirectPolicy::Follow)
+        .header("X-Application", &head_dict.request_header.application)
+        .header("X-Emby-Token", &head_dict.request_header.token);
+    if existing > 0 {
+        request = request.header("Range", format!("bytes={existing}-"));
+    }
+    let mut response = request
+        .body(()).map_err(|e| e.to_string())?
+        .send().map_err(|e| e.to_string())?;
+    let (mut output, mut received) = match response.status() {
+        StatusCode::PARTIAL_CONTENT => (fs::OpenOptions::new().append(true).open(file).map_err(|e| e.to_string())?, existing),
+        // The part file already contains everything, if it's as long as the server's file ("bytes */<total>").
+        StatusCode::RANGE_NOT_SATISFIABLE if existing > 0 => {
+            let total: Option<u64> = response.headers().get("Content-Range")
+                .and_then(|range| range.to_str().ok())
+                .and_then(|range| range.strip_prefix("bytes */"))
+                .and_then(|total| total.trim().parse::<u64>().ok());
+            if total == Some(existing) {
+                return Ok(())
+            }
+            fs::remove_file(file).map_err(|e| e.to_string())?;
+            return fetch_to_file(head_dict, url, file, cap, report)
+        },
+        status if status.is_success() => (fs::File::create(file).map_err(|e| e.to_string())?, 0),
+        status => return Err(format!("The server responded with {status}"))
+    };
+    let total: Option<u64> = response.body().len().map(|length| length + received);
+    let body = response.body_mut();
+    let mut buffer = [0; 65536];
+    let start = Instant::now();
+    let mut transferred: u64 = 0;
+    loop {
+        let read = body.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break
+        }
+        output.write_all(&buffer[..read]).map_err(|e| e.to_string())?;
+        received += read as u64;
+        transferred += read as u64;
+        report(received, total);
+        if let Some(cap) = cap {
+            let ahead = transferred as f64 / cap as f64 - start.elapsed().as_secs_f64();
+            if ahead > 0.0 {
+                thread::sleep(Duration::from_secs_f64(ahead));
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn ask_download_bitrate() -> u64 {
+    print!("Please enter the bitrate of the transcoded version in mbps: ");
+    let mut mbps: String = String::new();
+    loop {
+        io::stdout().flush().expect("Failed to flush stdout");
+        mbps.clear();
+        io::stdin().read_line(&mut mbps).unwrap();
+        match mbps.trim().parse::<f64>() {
+            Ok(mbps) if mbps > 0.0 => return (mbps * 1000000.0).round() as u64,
+            _ => print!("\nInvalid input! Enter something like \"4\".\n: ")
+        }
+    }
+}
+
+
+pub fn configure_rule(settings: &Settings, head_dict: &HeadDict, episodes: &[Items]) {
+    let Some(series_id) = episodes.first().and_then(|episode| episode.SeriesId.clone()) else { return };
+    let series_name = episodes[0].SeriesName.clone().unwrap_or_default();
+    print!("Which episodes of {} should be kept downloaded?\n  [1] The next unwatched episodes\n  [2] The latest unwatched episodes\n  [3] None (remove the rule)", series_name.cyan());
+    let latest = match getch("123") {
+        '1' => false,
+        '2' => true,
+        _ => {
+            let mut rules = read_rules(settings);
+            rules.retain(|rule| rule.series_id != series_id || rule.config_path != head_dict.config_path);
+            write_rules(settings, &rules);
+            println!("Removed the download rule of {}.", series_name.cyan());
+            return
+        }
+    };
+    print!("How many episodes?\n: ");
+    let mut count: String = String::new();
+    let count: usize = loop {
+        io::stdout().flush().expect("Failed to flush stdout");
+        count.clear();
+        io::stdin().read_line(&mut count).unwrap();
+        match count.trim().parse::<usize>() {
+            Ok(count) if count > 0 => break count,
+            _ => print!("\nInvalid input! Enter something like \"3\".\n: ")
+        }
+    };
+    let bitrate = ask_version(&series_name);
+    let mut rules = read_rules(settings);
+    rules.retain(|rule| rule.series_id != series_id || rule.config_path != head_dict.config_path);
+    rules.push(DownloadRule {
+        series_id,
+        series_name,
+        config_path: head_dict.config_path.clone(),
+        latest,
+        count,
+        bitrate
+    });
+    write_rules(settings, &rules);
+    apply_rules(settings, head_dict, episodes);
+}
+
+
+// Downloads, which have been watched in the meantime (with or without puddler), are deleted after logging in.
+pub fn remove_watched(settings: &Settings, head_dict: &HeadDict) {
+    let mut library = read_library(settings);
+    let ids: Vec<String> = library.iter().filter(|downloaded| downloaded.config_path == head_dict.config_path).map(|downloaded| downloaded.item.Id.clone()).collect();
+    let mut played: Vec<String> = Vec::new();
+    for chunk in ids.chunks(50) {
+        let response = puddler_get(format!("{}{}/Users/{}/Items?Ids={}",
+            head_dict.config_file.ipaddress, head_dict.media_server, head_dict.config_file.user_id, chunk.join(",")), head_dict);
+        if let Ok(items) = response.map_err(|e| e.to_string()).and_then(|mut response| response.json::<ItemJson>().map_err(|e| e.to_string())) {
+            played.extend(items.Items.into_iter().filter(|item| item.UserData.Played).map(|item| item.Id));
+        }
+    }
+    if played.is_empty() {
+        return
+    }
+    library.retain(|downloaded| {
+        if downloaded.config_path != head_dict.config_path || !played.contains(&downloaded.item.Id) {
+            return true
+        }
+        fs::remove_file(&downloaded.file).ok();
+        println!("Deleted the download of {}, since it has been watched.", downloaded.item.Name.cyan());
+        false
+    });
+    write_library(settings, &library);
+}
+
+
+// Queues whatever the rule of the series asks for and deletes watched episodes, as well as the ones the rule no longer wants.
+// Episodes downloaded by hand are only deleted once they're watched. The queue itself runs once the series is left.
+pub fn apply_rules(settings: &Settings, head_dict: &HeadDict, episodes: &[Items]) {
+    let Some(series_id) = episodes.first().and_then(|episode| episode.SeriesId.as_ref()) else { return };
+    let Some(rule) = read_rules(settings).into_iter().find(|rule| &rule.series_id == series_id && rule.config_path == head_dict.config_path) else { return };
+    let unwatched = episodes.iter().filter(|episode| !episode.UserData.Played);
+    let wanted: Vec<&Items> = if rule.latest {
+        let mut latest: Vec<&Items> = unwatched.rev().take(rule.count).collect();
+        latest.reverse();
+        latest
+    } else {
+        unwatched.take(rule.count).collect()
+    };
+    // The downloaded items still have the watched state of when they were downloaded, the episode list is up to date.
+    let is_played = |item: &Items| episodes.iter().any(|episode| episode.Id == item.Id && episode.UserData.Played);
+    let is_unneeded = |item: &Items, by_rule: bool| {
+        item.SeriesId.as_ref() == Some(series_id)
+            && (is_played(item) || (by_rule && !wanted.iter().any(|episode| episode.Id == item.Id)))
+    };
+
+    let mut library = read_library(settings);
+    library.retain(|downloaded| {
+        if !is_unneeded(&downloaded.item, downloaded.by_rule) {
+            return true
+        }
+        fs::remove_file(&downloaded.file).ok();
+        println!("Deleted the download of {}.", downloaded.item.Name.cyan());
+        false
+    });
+    write_library(settings, &library);
+    let mut queue = read_queue(settings);
+    queue.retain(|queued| {
+        if !is_unneeded(&queued.item, queued.by_rule) {
+            return true
+        }
+        fs::remove_file(format!("{}.part", queued.file)).ok();
+        false
+    });
+    write_queue(settings, &queue);
+
+    let added: usize = wanted.iter().filter(|episode| enqueue(settings, head_dict, episode, rule.bitrate, true)).count();
+    if added > 0 {
+        println!("Queued {} episode(s) of {} for download, they're downloaded once you leave the series.", added, rule.series_name.cyan());
+    }
+}
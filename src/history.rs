@@ -0,0 +1,171 @@
+// Records every playback session in a local SQLite database
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+use app_dirs::*;
+use colored::Colorize;
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::mediaserver_information::HeadDict;
+use crate::player::PlaybackState;
+use crate::progress_report::PlaySession;
+use crate::APP_INFO;
+
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    play_session_id TEXT NOT NULL UNIQUE,
+    item_id TEXT NOT NULL,
+    item_name TEXT NOT NULL,
+    item_type TEXT NOT NULL,
+    series_id TEXT,
+    series_name TEXT,
+    season_name TEXT,
+    server TEXT NOT NULL,
+    server_kind TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    username TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    stopped_at INTEGER,
+    start_position REAL NOT NULL,
+    last_position REAL NOT NULL,
+    runtime REAL,
+    play_method TEXT NOT NULL,
+    audio_stream_index INTEGER,
+    subtitle_stream_index INTEGER,
+    bitrate INTEGER,
+    played INTEGER NOT NULL DEFAULT 0
+)";
+
+
+// Progress is recorded on every tick, so the database is only opened (and its schema created) once.
+static CONNECTION: OnceLock<Option<Mutex<Connection>>> = OnceLock::new();
+
+
+fn open() -> Option<Connection> {
+    let folder = get_app_root(AppDataType::UserData, &APP_INFO).ok()?;
+    std::fs::create_dir_all(&folder).ok()?;
+    let connection = Connection::open(folder.join("history.sqlite")).ok()?;
+    connection.execute(SCHEMA, []).ok()?;
+    Some(connection)
+}
+
+
+pub fn connection() -> Option<MutexGuard<'static, Connection>> {
+    CONNECTION.get_or_init(|| open().map(Mutex::new)).as_ref()?.lock().ok()
+}
+
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+
+// The history is a nice-to-have, so failing to write it never interrupts playback.
+fn execute<P: rusqlite::Params>(sql: &str, params: P) {
+    if let Some(connection) = connection() {
+        if let Err(e) = connection.execute(sql, params) {
+            println!("{}", format!("Couldn't update the watch history: {e}").red());
+        }
+    }
+}
+
+
+pub fn record_start(head_dict: &HeadDict, session: &PlaySession, state: &PlaybackState) {
+    let item = session.item;
+    execute("INSERT OR IGNORE INTO sessions (play_session_id, item_id, item_name, item_type, series_name, season_name, server, server_kind, user_id, username,
+            started_at, start_position, last_position, runtime, play_method, audio_stream_index, subtitle_stream_index, bitrate, series_id)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+        params![
+            session.playsession_id,
+            item.Id,
+            item.Name,
+            item.Type,
+            item.SeriesName,
+            item.SeasonName,
+            head_dict.config_file.ipaddress,
+            head_dict.media_server_name,
+            head_dict.config_file.user_id,
+            head_dict.config_file.username,
+            now(),
+            state.position,
+            item.RunTimeTicks.map(|ticks| ticks as f64 / 10000000.0),
+            session.playmethod(),
+            state.audio_stream_index.map(|index| index as i64),
+            state.subtitle_stream_index.map(|index| index as i64),
+            session.bitrate.map(|bitrate| bitrate as i64),
+            item.SeriesId
+        ]
+    );
+}
+
+
+pub fn record_progress(session: &PlaySession, state: &PlaybackState) {
+    execute("UPDATE sessions SET last_position = ?2, audio_stream_index = ?3, subtitle_stream_index = ?4 WHERE play_session_id = ?1",
+        params![
+            session.playsession_id,
+            state.position,
+            state.audio_stream_index.map(|index| index as i64),
+            state.subtitle_stream_index.map(|index| index as i64)
+        ]
+    );
+}
+
+
+pub fn record_stop(session: &PlaySession, position: f64, played: bool) {
+    execute("UPDATE sessions SET stopped_at = ?2, last_position = ?3, played = ?4 WHERE play_session_id = ?1",
+        params![session.playsession_id, now(), position, played]
+    );
+}
+
+
+// The item of the most recent session that hasn't been finished yet.
+pub fn last_unfinished(head_dict: &HeadDict) -> Option<String> {
+    connection()?.query_row("SELECT item_id FROM sessions WHERE server = ?1 AND user_id = ?2 AND played = 0 ORDER BY started_at DESC LIMIT 1",
+        params![head_dict.config_file.ipaddress, head_dict.config_file.user_id],
+        |row| row.get(0)
+    ).optional().ok()?
+}
+
+
+pub fn print_history(head_dict: &HeadDict) {
+    let Some(connection) = connection() else {
+        println!("{}", "Couldn't open the watch history.".to_string().red());
+        return
+    };
+    let server = &head_dict.config_file.ipaddress;
+    let user_id = &head_dict.config_file.user_id;
+    let mut statement = connection.prepare("SELECT datetime(started_at, 'unixepoch', 'localtime'), item_name, series_name, season_name, last_position, runtime, play_method, played
+        FROM sessions WHERE server = ?1 AND user_id = ?2 ORDER BY started_at DESC LIMIT 20").unwrap();
+    let sessions = statement.query_map(params![server, user_id], |row| {
+        let started: String = row.get(0)?;
+        let name: String = row.get(1)?;
+        let series: Option<String> = row.get(2)?;
+        let season: Option<String> = row.get(3)?;
+        let position: f64 = row.get(4)?;
+        let runtime: Option<f64> = row.get(5)?;
+        let method: String = row.get(6)?;
+        let played: bool = row.get(7)?;
+        let title = match (series, season) {
+            (Some(series), Some(season)) => format!("{series} - {season} - {name}"),
+            _ => name
+        };
+        let progress = if played {
+            "[PLAYED]".to_string().green().to_string()
+        } else {
+            match runtime {
+                Some(runtime) if runtime > 0.0 => format!("{}%", (position / runtime * 100.0).round() as i64),
+                _ => format!("{}min", (position / 60.0).round() as i64)
+            }
+        };
+        Ok(format!("      {started} {title} ({method}) {progress}"))
+    }).unwrap();
+    let sessions: Vec<String> = sessions.flatten().collect();
+    if sessions.is_empty() {
+        println!("\nYour watch history is empty.");
+        return
+    }
+    println!("\nWatch history:");
+    for session in sessions.iter().rev() {
+        println!("{session}");
+    }
+    println!("Type \"STATS\" in the menu for more statistics.\n");
+}
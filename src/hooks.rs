@@ -0,0 +1,149 @@
+// Runs the user's own commands or webhooks when something happens during playback
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use colored::Colorize;
+use isahc::prelude::*;
+use isahc::Request;
+use serde_derive::{Deserialize, Serialize};
+use crate::mediaserver_information::HeadDict;
+use crate::settings::Settings;
+use crate::Items;
+
+
+// Every hook is either a shell command or a http(s) url, which receives the payload as JSON.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct Hooks {
+    pub start: Option<String>,
+    pub pause: Option<String>,
+    pub resume: Option<String>,
+    pub stop: Option<String>,
+    pub played: Option<String>
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub enum HookEvent {
+    Start,
+    Pause,
+    Resume,
+    Stop,
+    Played
+}
+
+
+impl HookEvent {
+    pub const ALL: [HookEvent; 5] = [HookEvent::Start, HookEvent::Pause, HookEvent::Resume, HookEvent::Stop, HookEvent::Played];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Start => "start",
+            HookEvent::Pause => "pause",
+            HookEvent::Resume => "resume",
+            HookEvent::Stop => "stop",
+            HookEvent::Played => "played"
+        }
+    }
+}
+
+
+impl Hooks {
+    pub fn get(&self, event: HookEvent) -> &Option<String> {
+        match event {
+            HookEvent::Start => &self.start,
+            HookEvent::Pause => &self.pause,
+            HookEvent::Resume => &self.resume,
+            HookEvent::Stop => &self.stop,
+            HookEvent::Played => &self.played
+        }
+    }
+
+    pub fn get_mut(&mut self, event: HookEvent) -> &mut Option<String> {
+        match event {
+            HookEvent::Start => &mut self.start,
+            HookEvent::Pause => &mut self.pause,
+            HookEvent::Resume => &mut self.resume,
+            HookEvent::Stop => &mut self.stop,
+            HookEvent::Played => &mut self.played
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize)]
+struct HookPayload {
+    event: String,
+    item_id: String,
+    item_name: String,
+    item_type: String,
+    series_name: Option<String>,
+    season_name: Option<String>,
+    position_seconds: f64,
+    runtime_seconds: Option<f64>,
+    server: String
+}
+
+
+impl HookPayload {
+    fn environment(&self) -> Vec<(&'static str, String)> {
+        [
+            ("PUDDLER_EVENT", self.event.clone()),
+            ("PUDDLER_ITEM_ID", self.item_id.clone()),
+            ("PUDDLER_ITEM_NAME", self.item_name.clone()),
+            ("PUDDLER_ITEM_TYPE", self.item_type.clone()),
+            ("PUDDLER_SERIES_NAME", self.series_name.clone().unwrap_or_default()),
+            ("PUDDLER_SEASON_NAME", self.season_name.clone().unwrap_or_default()),
+            ("PUDDLER_POSITION", self.position_seconds.round().to_string()),
+            ("PUDDLER_RUNTIME", self.runtime_seconds.map(|runtime| runtime.round().to_string()).unwrap_or_default()),
+            ("PUDDLER_SERVER", self.server.clone())
+        ].to_vec()
+    }
+}
+
+
+// Hooks never block playback, both commands and requests run in the background.
+pub fn run_hook(settings: &Settings, event: HookEvent, head_dict: &HeadDict, item: &Items, position: f64) {
+    let Some(hook) = settings.hooks.get(event) else { return };
+    let payload = HookPayload {
+        event: event.name().to_string(),
+        item_id: item.Id.clone(),
+        item_name: item.Name.clone(),
+        item_type: item.Type.clone(),
+        series_name: item.SeriesName.clone(),
+        season_name: item.SeasonName.clone(),
+        position_seconds: position,
+        runtime_seconds: item.RunTimeTicks.map(|ticks| ticks as f64 / 10000000.0),
+        server: head_dict.media_server_name.clone()
+    };
+    if hook.starts_with("http://") || hook.starts_with("https://") {
+        let url = hook.clone();
+        let body = serde_json::to_string(&payload).unwrap();
+        thread::spawn(move || {
+            let result = Request::post(&url)
+                .timeout(Duration::from_secs(5))
+                .header("Content-Type", "application/json")
+                .body(body)
+                .map_err(|e| e.to_string())
+                .and_then(|request| request.send().map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                println!("{}", format!("The {} hook ({url}) failed: {e}", payload.event).red());
+            }
+        });
+    } else {
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.args(["/C", hook]);
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.args(["-c", hook]);
+            command
+        };
+        match command.envs(payload.environment()).spawn() {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            },
+            Err(e) => println!("{}", format!("The {} hook couldn't be started: {e}", payload.event).red())
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// Keys inside of mpv, which send puddler a script-message instead of being handled by mpv itself
+use std::time::Duration;
+use colored::Colorize;
+use http::{Method, StatusCode};
+use isahc::prelude::*;
+use isahc::Request;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use crate::backend::Player;
+use crate::config::write_start_offset;
+use crate::mediaserver_information::HeadDict;
+use crate::Items;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Next,
+    Previous,
+    PlayedAndNext,
+    SkipIntro,
+    ToggleFavorite,
+    SetStartOffset
+}
+
+
+// Alt combinations aren't used by mpv's default bindings.
+// The same messages can be bound in input.conf, f.e. "N script-message puddler next".
+const BINDINGS: [(&str, &str, Action); 6] = [
+    ("Alt+n", "next", Action::Next),
+    ("Alt+p", "previous", Action::Previous),
+    ("Alt+m", "played-and-next", Action::PlayedAndNext),
+    ("Alt+i", "skip-intro", Action::SkipIntro),
+    ("Alt+f", "toggle-favorite", Action::ToggleFavorite),
+    ("Alt+o", "set-start-offset", Action::SetStartOffset)
+];
+
+
+#[derive(Debug, Deserialize)]
+struct Chapter {
+    StartPositionTicks: i64,
+    MarkerType: Option<String>
+}
+
+
+pub struct Keybindings {
+    favorite: bool,
+    // Only looked up once the intro should be skipped.
+    intro_end: Option<Option<f64>>
+}
+
+
+fn request(head_dict: &HeadDict, method: Method, path: String) -> Result<Value, String> {
+    let mut response = Request::builder()
+        .method(method)
+        .uri(format!("{}{}{}", head_dict.config_file.ipaddress, head_dict.media_server, path))
+        .timeout(Duration::from_secs(5))
+        .header("X-Application", &head_dict.request_header.application)
+        .header("X-Emby-Token", &head_dict.request_header.token)
+        .body(()).map_err(|e| e.to_string())?
+        .send().map_err(|e| e.to_string())?;
+    match response.status() {
+        StatusCode::OK | StatusCode::NO_CONTENT => Ok(response.json::<Value>().unwrap_or(Value::Null)),
+        status => Err(status.to_string())
+    }
+}
+
+
+// Emby marks the intro with chapters, Jellyfin (10.10+) with media segments.
+fn intro_end(head_dict: &HeadDict, item: &Items) -> Option<f64> {
+    let ticks: i64 = if head_dict.media_server_name == "Emby" {
+        let item = request(head_dict, Method::GET, format!("/Users/{}/Items/{}", head_dict.config_file.user_id, item.Id)).ok()?;
+        let chapters: Vec<Chapter> = serde_json::from_value(item["Chapters"].clone()).ok()?;
+        chapters.into_iter().find(|chapter| chapter.MarkerType.as_deref() == Some("IntroEnd"))?.StartPositionTicks
+    } else {
+        let segments = request(head_dict, Method::GET, format!("/MediaSegments/{}?includeSegmentTypes=Intro", item.Id)).ok()?;
+        segments["Items"].as_array()?.first()?["EndTicks"].as_i64()?
+    };
+    Some(ticks as f64 / 10000000.0)
+}
+
+
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+
+impl Keybindings {
+    // mpv versions before 0.37 don't know "keybind", the bindings are defined as an input section there.
+    pub fn register(mpv: &mut dyn Player, item: &Items) -> Keybindings {
+        let registered = BINDINGS.iter().all(|(key, message, _)| mpv.command(&["keybind", key, &format!("script-message puddler {message}")]).is_ok());
+        if !registered {
+            let section: String = BINDINGS.iter().map(|(key, message, _)| format!("{key} script-message puddler {message}\n")).collect();
+            if mpv.command(&["define-section", "puddler", &section, "force"]).is_ok() {
+                mpv.command(&["enable-section", "puddler"]).ok();
+            }
+        }
+        Keybindings {
+            favorite: item.UserData.IsFavorite,
+            intro_end: None
+        }
+    }
+
+    // Actions, which end the playback, are returned to the playback loop, everything else is handled right here.
+    pub fn handle(&mut self, args: &[String], head_dict: &HeadDict, item: &Items, position: f64, mpv: &mut dyn Player) -> Option<Action> {
+        let action: Action = match args {
+            [target, message] if target == "puddler" => BINDINGS.iter().find(|(_, name, _)| name == message)?.2,
+            _ => return None
+        };
+        let message: String = match action {
+            Action::Next | Action::Previous | Action::PlayedAndNext => return Some(action),
+            Action::SkipIntro => {
+                let intro_end = *self.intro_end.get_or_insert_with(|| intro_end(head_dict, item));
+                match intro_end {
+                    Some(end) if position < end => {
+                        mpv.command(&["seek", &end.to_string(), "absolute"]).ok();
+                        format!("Skipped the intro ({})", format_time(end))
+                    },
+                    Some(_) => "The intro is already over".to_string(),
+                    None => "The server doesn't know where the intro ends".to_string()
+                }
+            },
+            Action::ToggleFavorite => {
+                let method = if self.favorite { Method::DELETE } else { Method::POST };
+                match request(head_dict, method, format!("/Users/{}/FavoriteItems/{}", head_dict.config_file.user_id, item.Id)) {
+                    Ok(_) => {
+                        self.favorite = !self.favorite;
+                        if self.favorite { "Added to your favorites".to_string() } else { "Removed from your favorites".to_string() }
+                    },
+                    Err(e) => format!("Couldn't change the favorite: {e}")
+                }
+            },
+            Action::SetStartOffset => match (&item.SeriesId, &item.SeriesName) {
+                (Some(series_id), Some(series_name)) => {
+                    write_start_offset(&head_dict.config_path, series_id, position.round() as i32);
+                    format!("Episodes of {series_name} will start at {}", format_time(position))
+                },
+                _ => "Only episodes of a series can have a start offset".to_string()
+            }
+        };
+        println!("{}", message.cyan());
+        mpv.command(&["show-text", &message]).ok();
+        None
+    }
+}
@@ -8,26 +8,41 @@ use isahc::Body;
 use isahc::Request;
 use progress_report::MediaSourceInfo;
 use regex::Regex;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::io;
 use std::io::prelude::*;
 use std::io::stdin;
 use std::process;
-use std::process::Child;
-use std::process::Command;
 use std::process::ExitCode;
-use std::thread;
 use std::time::Duration;
-use sysinfo::System;
 use urlencoding::encode;
+pub mod arguments;
+pub mod backend;
+pub mod bandwidth;
+pub mod binge;
 pub mod config;
 pub mod discord;
+pub mod downloads;
+pub mod history;
+pub mod hooks;
+pub mod keybindings;
+pub mod migrate;
 pub mod mediaserver_information;
+pub mod mpris;
+pub mod mpv_ipc;
+pub mod mpv_options;
 pub mod player;
+pub mod players;
+pub mod prefetch;
+pub mod presence;
 mod progress_report;
 pub mod settings;
+pub mod shuffle;
+pub mod stats;
+use downloads::{apply_rules, configure_rule, download_item, read_library, remove_watched, resume_queue, DownloadedItem};
 use mediaserver_information::*;
-use player::play;
+use prefetch::{Episode, PrefetchPool};
+use player::{auth_header_field, choose_media_source, choose_trackIndexx, play_queue, PlaybackEnd};
 use settings::*;
 const APPNAME: &str = "Puddler";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -43,7 +58,7 @@ struct ItemJson {
     TotalRecordCount: Option<u16>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct Items {
     pub Name: String,
     pub Id: String,
@@ -54,15 +69,21 @@ pub struct Items {
     pub SeriesId: Option<String>,
     pub SeasonName: Option<String>,
     pub SeasonId: Option<String>,
+    pub IndexNumber: Option<u32>,
+    pub ParentIndexNumber: Option<u32>,
     pub PremiereDate: Option<String>,
     pub MediaSources: Option<Vec<MediaSourceInfo>>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct UserData {
     pub PlayedPercentage: Option<f64>,
     pub PlaybackPositionTicks: i64,
     pub Played: bool,
+    pub PlayCount: Option<u64>,
+    pub LastPlayedDate: Option<String>,
+    #[serde(default)]
+    pub IsFavorite: bool,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone)]
@@ -86,6 +107,8 @@ struct SeasonStruct {
 }
 
 fn main() -> ExitCode {
+    arguments::get();
+    backend::handle_interrupt();
     let mut settings: Settings = initialize_settings(0);
     println!(
         "{}",
@@ -100,8 +123,8 @@ fn main() -> ExitCode {
     println!();
     loop {
         if settings.server_config.is_some() {
-            print!("  [ENTER] Stream from default media-server\n  [1] Stream from either Emby or Jellyfin\n  [2] Change puddlers default settings\n  [3] Display current settings\n  [E] Exit puddler");
-            let menu = getch("123Ee\n");
+            print!("  [ENTER] Stream from default media-server\n  [1] Stream from either Emby or Jellyfin\n  [2] Change puddlers default settings\n  [3] Display current settings\n  [4] Watch downloaded items\n  [5] Migrate watched state between servers\n  [E] Exit puddler");
+            let menu = getch("12345Ee\n");
             match menu {
                 '\n' => break,
                 '1' => {
@@ -114,14 +137,20 @@ fn main() -> ExitCode {
                 '3' => {
                     settings = initialize_settings(2);
                 }
+                '4' => {
+                    offline_library(&settings);
+                }
+                '5' => {
+                    migrate::migrate_watched_state();
+                }
                 'e' | 'E' => {
                     process::exit(0x0100);
                 }
                 _ => (),
             };
         } else {
-            print!("  [1] Stream from either Emby or Jellyfin\n  [2] Change puddlers default settings\n  [3] Display current settings\n  [E] Exit puddler");
-            let menu = getch("123Ee");
+            print!("  [1] Stream from either Emby or Jellyfin\n  [2] Change puddlers default settings\n  [3] Display current settings\n  [4] Watch downloaded items\n  [5] Migrate watched state between servers\n  [E] Exit puddler");
+            let menu = getch("12345Ee");
             match menu {
                 '1' => break,
                 '2' => {
@@ -130,6 +159,12 @@ fn main() -> ExitCode {
                 '3' => {
                     settings = initialize_settings(2);
                 }
+                '4' => {
+                    offline_library(&settings);
+                }
+                '5' => {
+                    migrate::migrate_watched_state();
+                }
                 'e' | 'E' => {
                     process::exit(0x0100);
                 }
@@ -137,15 +172,83 @@ fn main() -> ExitCode {
             };
         }
     }
-    if let Some(head_dict) = check_information(&settings) {
+    if let Some(head_dict) = check_information(&mut settings) {
+        progress_report::flush_pending_reports(&head_dict);
+        remove_watched(&settings, &head_dict);
+        resume_queue(&settings, &head_dict);
         loop {
             choose_and_play(&head_dict, &settings);
         }
+    } else if !read_library(&settings).is_empty() {
+        println!("You can still watch your downloaded items.\n");
+        offline_library(&settings);
+        ExitCode::FAILURE
     } else {
         ExitCode::FAILURE
     }
 }
 
+fn offline_library(settings: &Settings) {
+    loop {
+        let library: Vec<DownloadedItem> = read_library(settings);
+        if library.is_empty() {
+            println!("\nYou haven't downloaded anything yet.\n");
+            return;
+        }
+        println!("\nDownloaded items:");
+        for (index, downloaded) in library.iter().enumerate() {
+            let item = &downloaded.item;
+            if item.Type == "Episode" {
+                println!(
+                    "      [{}] {} - {} - {}",
+                    index,
+                    item.SeriesName.as_ref().unwrap(),
+                    item.SeasonName.as_ref().unwrap(),
+                    item.Name
+                );
+            } else {
+                println!(
+                    "      [{}] {} ({})",
+                    index,
+                    item.Name,
+                    &item.PremiereDate.as_ref().unwrap_or(&"????".to_string())[0..4]
+                );
+            }
+        }
+        print!("Please choose which item you want to play, or press ENTER to return.\n: ");
+        io::stdout().flush().expect("Failed to flush stdout");
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        if input.trim().is_empty() {
+            return;
+        }
+        let downloaded: &DownloadedItem = match input.trim().parse::<usize>() {
+            Ok(pick) if pick < library.len() => &library[pick],
+            _ => {
+                println!("{}", "Are you ok?!".red());
+                continue;
+            }
+        };
+        // Downloaded episodes of the same series are queued, so next/previous work offline too.
+        let queue: Vec<Items> = match &downloaded.item.SeriesId {
+            Some(series_id) => library
+                .iter()
+                .filter(|other| other.item.SeriesId.as_ref() == Some(series_id))
+                .map(|other| other.item.clone())
+                .collect(),
+            None => vec![downloaded.item.clone()],
+        };
+        let position = queue.iter().position(|queued| queued.Id == downloaded.item.Id).unwrap();
+        match offline_head_dict(&downloaded.config_path) {
+            Some(head_dict) => play_queue(settings, &head_dict, &queue, position),
+            None => println!(
+                "{}",
+                "The server configuration of this download is missing.".to_string().red()
+            ),
+        }
+    }
+}
+
 fn choose_and_play(head_dict: &HeadDict, settings: &Settings) {
     let ipaddress = &head_dict.config_file.ipaddress;
     let media_server = &head_dict.media_server;
@@ -227,13 +330,56 @@ fn choose_and_play(head_dict: &HeadDict, settings: &Settings) {
         }
         item_list = print_menu(&latest_response, true, item_list);
     }
-    print!("Please choose from above, enter a search term, or type \"ALL\" to display literally everything.\n: ");
+    print!("Please choose from above, enter a search term, type \"ALL\" to display literally everything, \"LAST\" to continue your last item, \"HISTORY\" or \"STATS\" to see what you've watched, \"SHUFFLE\" (optionally followed by a series) to play random items, or \"D\" followed by a number to download an item.\n: ");
     io::stdout().flush().expect("Failed to flush stdout");
     let mut input = String::new();
     io::stdin().read_line(&mut input).unwrap();
 
     // processing input
-    if input.trim() == "ALL" {
+    let download = Regex::new(r"^[Dd](\d+)$").unwrap();
+    if let Some(caps) = download.captures(input.trim()) {
+        match caps[1].parse::<usize>().ok().and_then(|pick| item_list.get(pick)) {
+            Some(item) if item.Type != "Series" => download_item(settings, head_dict, item),
+            Some(_) => println!("\nPlease pick the episodes of a series one by one."),
+            None => println!("{}", "Are you ok?!".red()),
+        }
+        return;
+    } else if input.trim() == "HISTORY" {
+        history::print_history(head_dict);
+        return;
+    } else if input.trim() == "STATS" {
+        stats::print_stats(head_dict);
+        return;
+    } else if input.trim() == "SHUFFLE" {
+        shuffle::shuffle_library(settings, head_dict);
+        return;
+    } else if let Some(search) = input.trim().strip_prefix("SHUFFLE ") {
+        shuffle::shuffle_series(settings, head_dict, search.trim());
+        return;
+    } else if input.trim() == "LAST" {
+        let Some(item_id) = history::last_unfinished(head_dict) else {
+            println!("\nThere is nothing to continue.");
+            return;
+        };
+        let last = puddler_get(
+            format!(
+                "{}{}/Users/{}/Items/{}?Fields=PremiereDate,MediaSources",
+                &ipaddress, &media_server, &user_id, &item_id
+            ),
+            head_dict,
+        );
+        // The item might have been deleted from the server since.
+        let last: Option<Items> = last
+            .ok()
+            .and_then(|mut t| t.text().ok())
+            .and_then(|response_text| serde_json::from_str(&response_text).ok());
+        let Some(last) = last else {
+            println!("\nThere is nothing to continue.");
+            return;
+        };
+        item_list = vec![last];
+        pick = Some(0);
+    } else if input.trim() == "ALL" {
         let all = puddler_get(format!("{}{}/Items?UserId={}&Recursive=true&IncludeItemTypes=Series,Movie&Fields=PremiereDate,MediaSources&collapseBoxSetItems=False", &ipaddress, &media_server, &user_id), head_dict);
         let all_response: ItemJson = match all {
             Ok(mut t) => {
@@ -391,7 +537,7 @@ fn item_parse(head_dict: &HeadDict, item_list: &[Items], pick: i32, settings: &S
 
     if item_list.get(pick as usize).unwrap().Type == *"Movie" {
         let item = item_list.get(pick as usize).unwrap();
-        play(settings, head_dict, item);
+        play_queue(settings, head_dict, std::slice::from_ref(item), 0);
     } else if item_list.get(pick as usize).unwrap().Type == *"Series" {
         let series = &item_list.get(pick as usize).unwrap();
         println!("{}:", series.Name);
@@ -457,21 +603,6 @@ fn item_parse(head_dict: &HeadDict, item_list: &[Items], pick: i32, settings: &S
     }
 }
 
-fn mpv播放(播放开始时间_秒: &i32, 内容: &str, 标题: &str, 字幕: &str) -> Child {
-    let mpv = Command::new(r"E:\video\mpv_config-latest\mpv.exe")
-        .args(&[
-            内容,
-            (format!("--start={}", 播放开始时间_秒)).as_str(),
-            "--fs",
-            "--idle=once",
-            "--pause",
-            (format!("--force-media-title={}", 标题)).as_str(),
-            (format!("--sub-file={}", 字幕)).as_str(),
-            // ={sub_file}
-        ])
-        .spawn();
-    mpv.unwrap()
-}
 fn 获取整数输入() -> i32 {
     println!("请输入整数");
     let mut input = String::new();
@@ -493,66 +624,93 @@ fn 提取整数(输入: &str) -> i32 {
 }
 use glob::glob;
 
-fn 寻找匹配的字幕(季: i32, 集: &String) -> String {
-    let 字幕文件夹 = r"R:\sub_out\";
+fn 寻找匹配的字幕(字幕文件夹: &str, 季: i32, 集: &String) -> Option<String> {
     let 需要的字幕 = format!("*S*{}E{}*.ass", 季, 集);
-    let 需要的字幕匹配表达式 = format!("{}{}", 字幕文件夹, 需要的字幕);
-    let 匹配的字幕列表 = glob(需要的字幕匹配表达式.as_str()).unwrap();
+    let 需要的字幕匹配表达式 = std::path::Path::new(字幕文件夹).join(需要的字幕);
+    let 匹配的字幕列表 = glob(需要的字幕匹配表达式.to_str()?).ok()?;
     let mut 字幕列表: Vec<std::path::PathBuf> = Vec::new();
-    for entry in 匹配的字幕列表 {
-        // println!("{}", entry.unwrap().display());
-        字幕列表.push(entry.unwrap());
+    for entry in 匹配的字幕列表.flatten() {
+        字幕列表.push(entry);
     }
     if 字幕列表.len() == 1 {
-        字幕列表[0].to_str().unwrap().to_string()
+        字幕列表[0].to_str().map(|字幕| 字幕.to_string())
     } else {
-        "None".to_string()
+        None
     }
-    // println!("{}", 匹配的字幕列表[0]);
 }
 
-fn 获取程序数量(目标数量: i32) -> bool {
-    let mut s = System::new_all();
-    loop {
-        std::thread::sleep(Duration::from_secs(30));
-        s.refresh_processes();
-        let 程序查找 = s.processes_by_name("mpv.exe");
-        let mut 数量 = 0;
-        for _process in 程序查找 {
-            数量 += 1
-        }
-        if 数量 < 目标数量.try_into().unwrap() {
-            break;
-        }
+fn 剧集(item_list: &[Items], index: usize, 字幕偏移集数: i32, 播放开始时间_秒: i32, head_dict: &HeadDict, settings: &Settings) -> Option<Episode> {
+    let item = item_list.get(index)?;
+    let 当前季数 = 提取整数(item.SeasonName.as_ref().unwrap());
+    let 当前集数 = format!("{:0>2}", index as i32 + 字幕偏移集数);
+    // 没设置字幕文件夹就不找字幕
+    let 当前字幕 = settings.subtitle_folder.as_deref().and_then(|字幕文件夹| 寻找匹配的字幕(字幕文件夹, 当前季数, &当前集数));
+    // 和普通播放一样选版本和音轨/字幕, 服务器的流序号要换成mpv的轨道号
+    let 版本 = choose_media_source(settings, item)?;
+    let (音轨, 字幕轨) = choose_trackIndexx(&版本);
+    let mut 选项 = mpv_options::options_for(settings, head_dict, item);
+    if let Some(aid) = 版本.mpv_track("Audio", 音轨) {
+        选项.push(("aid".to_string(), aid.to_string()));
+    }
+    // 本地字幕优先
+    if let Some(sid) = 版本.mpv_track("Subtitle", 字幕轨).filter(|_| 当前字幕.is_none()) {
+        选项.push(("sid".to_string(), sid.to_string()));
+    }
+    // 有服务器上的进度就从那里继续, 否则跳过片头
+    let 开始时间_秒 = if item.UserData.PlaybackPositionTicks > 0 {
+        (item.UserData.PlaybackPositionTicks / 10000000) as i32
+    } else {
+        播放开始时间_秒
+    };
+    Some(Episode {
+        url: format!(
+            "{}{}/Videos/{}/stream?Container=mkv&Static=true&MediaSourceId={}",
+            head_dict.config_file.ipaddress, head_dict.media_server, item.Id, 版本.Id
+        ),
+        title: format!(
+            "{} - {} S{}E{}",
+            item.Name,
+            item.SeriesName.as_ref().unwrap(),
+            &当前季数,
+            &当前集数
+        ),
+        subtitle: 当前字幕,
+        header: auth_header_field(head_dict),
+        start: 开始时间_秒,
+        media_source: 版本,
+        options: 选项
+    })
+}
+
+// MPRIS "previous" goes back one episode, everything else continues with the next one.
+fn 下一集(pick: i32, end: PlaybackEnd) -> i32 {
+    match end {
+        PlaybackEnd::Previous => (pick - 1).max(0),
+        _ => pick + 1,
     }
-    return true;
 }
 
 fn series_play(item_list: &Vec<Items>, mut pick: i32, head_dict: &HeadDict, settings: &Settings) {
     let episode_amount: i32 = item_list.len().try_into().unwrap();
     // TODO 这个集数判断输出是900+ 是总集数(所有季) 但确实在pick +2 的判断 判断出了季的分割点 此时pick应在190左右
     // TODO 可能是某种打开方式只判断了单季 无论是搜索还是 等待观看进入都没复现
-    // let item = &item_list.get(pick as usize).unwrap();
     //TODO 集数偏移 用于匹配字幕 实测偏移了 1 就不用 确定季数了 这里显示的6 字幕用的7 哦 index从0开始的 默认给偏移为1
-    // TODO mpv播放器始终3个 然后把字幕给实现了就行了
-    let mut 自定义播放开始时间_秒 = 140;
-    let mut 初始化多进程播放 = 0;
+    // 片头偏移按剧保存在服务器配置里, 播放中也能用快捷键设置
+    let 剧id = item_list.get(pick as usize).and_then(|item| item.SeriesId.clone()).unwrap_or_default();
+    let mut 自定义播放开始时间_秒 = config::read_start_offset(&head_dict.config_path, &剧id).unwrap_or(140);
     let mut 多进程播放状态 = false;
     let mut 字幕偏移集数 = 1;
-    // let 播放地址 = format!("{}{}/Videos/{}/stream?Container=mkv&Static=true&api_key={}",head_dict.config_file.ipaddress, head_dict.media_server, item.Id, head_dict.request_header.token);
-    // let 当前播放 = mpv播放(&播放地址,&标题);
-    let 一直往后缓存的集数 = 2;
-    let 总进程数 = 一直往后缓存的集数 + 1;
-
-    // for _x in 1..=一直往后缓存的集数{
-    //   pick += 1;
-    //   if item_list.get(pick as usize).is_some() {
-    //     let item = &item_list.get(pick as usize).unwrap();
-    //     let 播放地址 = format!("{}{}/Videos/{}/stream?Container=mkv&Static=true&api_key={}",head_dict.config_file.ipaddress, head_dict.media_server, item.Id, head_dict.request_header.token);
-    //     mpv播放(&播放地址,&标题);}
-    // }
-    // play(settings, head_dict, item);
+    // 连播被取消后不再自动开始, 而是回到选择
+    let mut 连播已停止 = false;
+    // 后面的几集已经在暂停的mpv里加载好了, 返回菜单时会全部关闭
+    let mut pool = PrefetchPool::new(settings);
+    apply_rules(settings, head_dict, item_list);
+
     loop {
+        if let Some(偏移) = config::read_start_offset(&head_dict.config_path, &剧id).filter(|偏移| *偏移 != 自定义播放开始时间_秒) {
+            自定义播放开始时间_秒 = 偏移;
+            pool.unload();
+        }
         if (pick + 1) > episode_amount {
             // +1 since episode_amount doesn't start at 0 AND +1 for next ep 多次+2却忽略了最后一集改为1
             println!("{} {}", pick, episode_amount);
@@ -562,9 +720,6 @@ fn series_play(item_list: &Vec<Items>, mut pick: i32, head_dict: &HeadDict, sett
         } else {
             if item_list.get(pick as usize).is_some() {
                 let next_item = &item_list.get(pick as usize).unwrap();
-                // if next_item.UserData.Played {
-                //     continue;
-                // };
                 let 当前季数 = 提取整数(next_item.SeasonName.as_ref().unwrap());
                 let 当前集数 = format!("{:0>2}", pick + 字幕偏移集数);
                 let 标题 = format!(
@@ -575,58 +730,46 @@ fn series_play(item_list: &Vec<Items>, mut pick: i32, head_dict: &HeadDict, sett
                     &当前集数
                 )
                 .cyan();
-                let 当前字幕 = 寻找匹配的字幕(当前季数, &当前集数);
-                let 播放地址 = format!(
-                    "{}{}/Videos/{}/stream?Container=mkv&Static=true&api_key={}",
-                    head_dict.config_file.ipaddress,
-                    head_dict.media_server,
-                    next_item.Id,
-                    head_dict.request_header.token
-                );
-                // println!("{:?}", &next_item);
-                if 多进程播放状态 && 初始化多进程播放 <= 一直往后缓存的集数
-                {
-                    pick += 1;
-                    mpv播放(&自定义播放开始时间_秒, &播放地址, &标题, &当前字幕);
-                    初始化多进程播放 += 1;
-                    println!("初始化多进程播放 {}", &标题);
-                    thread::sleep(Duration::from_secs(20));
-                    // 等待以确保窗口按剧集顺序排列
+                let 剧集表 = move |index: usize| 剧集(item_list, index, 字幕偏移集数, 自定义播放开始时间_秒, head_dict, settings);
+                if 多进程播放状态 {
+                    println!("连续播放 {}", &标题);
+                    match pool.play(settings, head_dict, item_list, pick as usize, 剧集表) {
+                        Some(end) => pick = 下一集(pick, end),
+                        // 启动失败时停在这一集, 回到选择
+                        None => 多进程播放状态 = false,
+                    }
                     continue;
                 };
-                if 初始化多进程播放 == 总进程数 {
-                    // 初始化多进程完毕后运行的语句
-                    let 需放下一集了 = 获取程序数量(总进程数);
-                    if 需放下一集了 {
-                        pick += 1;
-                        mpv播放(&自定义播放开始时间_秒, &播放地址, &标题, &当前字幕);
-                        println!("缓冲 {}", &标题);
-
-                        continue;
-                    }
-                }
-                if settings.autoplay {
-                    println!("\nWelcome back. Continuing in 3 seconds:\n{}", &标题);
-
-                    thread::sleep(Duration::from_secs(3));
-                    pick += 1;
-                    mpv播放(&自定义播放开始时间_秒, &播放地址, &标题, &当前字幕);
+                if settings.autoplay && !连播已停止 {
+                    pool.unload();
+                    pick = binge::binge(settings, head_dict, item_list, pick as usize, None) as i32;
+                    连播已停止 = true;
                 } else {
                     println!(
                         "\nWelcome back. Do you want to continue playback with:\n{}",
                         标题
                     );
-                    print!(" (N)ext | (M)enu | (E)xit");
-                    let cont = getch("NnDdSsFfCcAaPpEeMm");
+                    print!(" (N)ext | (B)inge | (L)oad for offline | (R)ules for downloads | (M)enu | (E)xit");
+                    let cont = getch("NnBbLlRrDdSsFfCcAaPpEeMm");
                     match cont {
                         'N' | 'n' => {
-                            pick += 1;
-                            mpv播放(&自定义播放开始时间_秒, &播放地址, &标题, &当前字幕);
+                            if let Some(end) = pool.play(settings, head_dict, item_list, pick as usize, 剧集表) {
+                                pick = 下一集(pick, end);
+                            }
+                        }
+                        'B' | 'b' => {
+                            pool.unload();
+                            pick = binge::binge(settings, head_dict, item_list, pick as usize, None) as i32;
+                        }
+                        'L' | 'l' => {
+                            download_item(settings, head_dict, next_item);
+                        }
+                        'R' | 'r' => {
+                            configure_rule(settings, head_dict, item_list);
                         }
                         'D' | 'd' => {
+                            // 不再询问, 每集结束后直接播放已缓冲好的下一集
                             多进程播放状态 = true;
-                            初始化多进程播放 = 0;
-                            // 即使没有播放每次循环都把pick相加了
                         }
                         'S' | 's' => {
                             println!(
@@ -639,9 +782,12 @@ fn series_play(item_list: &Vec<Items>, mut pick: i32, head_dict: &HeadDict, sett
                         'F' | 'f' => {
                             println!("当前开始播放秒 {} eg:140", &自定义播放开始时间_秒);
                             自定义播放开始时间_秒 = 获取整数输入();
+                            config::write_start_offset(&head_dict.config_path, &剧id, 自定义播放开始时间_秒);
+                            pool.unload();
                         }
                         'C' | 'c' => {
                             字幕偏移集数 = 获取整数输入();
+                            pool.unload();
                         }
                         'A' | 'a' => {
                             println!("{}", &episode_amount)
@@ -653,17 +799,15 @@ fn series_play(item_list: &Vec<Items>, mut pick: i32, head_dict: &HeadDict, sett
                                 println!("名 {:?} id {:?}", i, i.Id);
                                 // println!("名 {:?} id {:?} 播放id {:?}",i.Name,i.Id,i.MediaSources.as_ref().unwrap()[0].Id);
                                 let 播放地址 = format!(
-                                    "{}{}/Videos/{}/stream?Container=mkv&Static=true&api_key={}",
-                                    head_dict.config_file.ipaddress,
-                                    head_dict.media_server,
-                                    i.Id,
-                                    head_dict.request_header.token
+                                    "{}{}/Videos/{}/stream?Container=mkv&Static=true",
+                                    head_dict.config_file.ipaddress, head_dict.media_server, i.Id
                                 );
                                 println!("{}", &播放地址);
                             }
                         }
                         'M' | 'm' => break,
                         'E' | 'e' => {
+                            backend::kill_all();
                             process::exit(0x0100);
                         }
                         _ => (),
@@ -674,6 +818,9 @@ fn series_play(item_list: &Vec<Items>, mut pick: i32, head_dict: &HeadDict, sett
             }
         }
     }
+    // 规则排队的下载在离开剧集后才开始, 不会挡住播放
+    pool.unload();
+    resume_queue(settings, head_dict);
 }
 
 fn process_series(series: &SeriesStruct, head_dict: &HeadDict, printing: bool) -> Vec<Items> {
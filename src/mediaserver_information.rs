@@ -1,9 +1,13 @@
 #![allow(non_snake_case)]
 extern crate getch;
 use std::char;
+use std::collections::HashMap;
 use std::io;
 use std::io::prelude::*;
 use std::time::Duration;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
 use std::net::UdpSocket;
 use std::str::from_utf8;
 use std::result::Result;
@@ -23,8 +27,9 @@ use serde_derive::{Deserialize,Serialize};
 use crate::APPNAME;
 use crate::VERSION;
 use crate::APP_INFO;
-use crate::settings::Settings;
+use crate::settings::{Settings, SettingsOverrides};
 use crate::config::*;
+use crate::progress_report::{server_configuration, ServerConfiguration};
 
 
 #[derive(Debug)]
@@ -34,7 +39,11 @@ pub struct HeadDict {
   pub config_file: ConfigFile,
   pub auth_header: AuthHeader,
   pub request_header: RequestHeader,
-  pub session_id: String
+  pub session_id: String,
+  pub config_path: String,
+  pub offline: bool,
+  // The server's resume thresholds, empty when offline or not allowed to read them.
+  pub server_configuration: ServerConfiguration
 }
 
 
@@ -55,7 +64,21 @@ pub struct ConfigFileRaw {
   pub emby: bool,
   pub ipaddress: String,
   pub device_id: String,
-  pub user: Vec<ConfigFileUser>
+  pub user: Vec<ConfigFileUser>,
+  #[serde(default)]
+  pub bitrate_test: Option<BitrateTest>,
+  #[serde(default)]
+  pub overrides: SettingsOverrides,
+  // Where episodes of a series start, by series id.
+  #[serde(default)]
+  pub start_offsets: HashMap<String, i32>
+}
+
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BitrateTest {
+  pub bitrate: u64,
+  pub timestamp: u64
 }
 
 
@@ -87,12 +110,50 @@ pub struct AuthHeader {
 }
 
 
+// A key, which is still being waited for in the background (f.e. after a countdown ran out), goes to the next prompt.
+static PENDING_KEY: Mutex<Option<Receiver<u8>>> = Mutex::new(None);
+
+
+fn read_key() -> u8 {
+  match PENDING_KEY.lock().unwrap().take() {
+    Some(pending) => pending.recv().unwrap_or(b'\n'),
+    None => getch::Getch::new().getch().unwrap()
+  }
+}
+
+
+// Like getch, but gives up after the timeout.
+pub fn key_within(timeout: Duration) -> Option<char> {
+  let mut pending = PENDING_KEY.lock().unwrap();
+  let receiver = pending.get_or_insert_with(|| {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+      if let Ok(key) = getch::Getch::new().getch() {
+        sender.send(key).ok();
+      }
+    });
+    receiver
+  });
+  match receiver.recv_timeout(timeout) {
+    Ok(key) => {
+      *pending = None;
+      Some(key as char)
+    },
+    Err(RecvTimeoutError::Timeout) => None,
+    Err(RecvTimeoutError::Disconnected) => {
+      *pending = None;
+      None
+    }
+  }
+}
+
+
 pub fn getch(allowed: &str) -> char {
   let output: char;
   loop {
     print!("\n: ");
     io::stdout().flush().expect("Failed to flush stdout");
-    let ch: char = getch::Getch::new().getch().unwrap() as char;
+    let ch: char = read_key() as char;
     if allowed.contains(ch) {
       if ch == '\n' {
         println!("\n");
@@ -113,7 +174,7 @@ pub fn getch(allowed: &str) -> char {
 }
 
 
-pub fn check_information(settings: &Settings) -> Option<HeadDict> {
+pub fn check_information(settings: &mut Settings) -> Option<HeadDict> {
   let media_server: &str;
   let emby: bool;
   let media_server_name: &str;
@@ -169,11 +230,13 @@ pub fn check_information(settings: &Settings) -> Option<HeadDict> {
   let server_id: String;
   let mut device_id = uuid::Uuid::new_v4().to_string();
   let config_file: ConfigFile;
+  let used_config_path: String;
   if let Some(config_path_string) = config_path {
     println!("{}", "Configuration files found!".to_string().green());
-    let config_file_raw: Result<(ConfigFile, ConfigFileRaw), (Option<ConfigFileRaw>, &str)> = read_config(&config_path_string, settings.autologin);
+    let config_file_raw: Result<(ConfigFile, ConfigFileRaw), ConfigError> = read_config(&config_path_string, settings.autologin);
     match config_file_raw {
       Ok((mut file, mut raw_file)) => {
+        used_config_path = config_path_string.clone();
         let ipaddress = &file.ipaddress;
         device_id = file.device_id.clone();
         auth_header = AuthHeader {
@@ -236,6 +299,7 @@ pub fn check_information(settings: &Settings) -> Option<HeadDict> {
         };
       },
       Err((Some(mut file), "add user")) => {
+        used_config_path = config_path_string.clone();
         let ipaddress = file.ipaddress;
         let user_login = configure_new_login(media_server_name);
         let auth = test_auth(media_server_name, media_server, &ipaddress, &auth_header, &user_login, &device_id);
@@ -292,7 +356,8 @@ pub fn check_information(settings: &Settings) -> Option<HeadDict> {
             username: user_login.username
           };
           let config_path_string = generate_config_path(server_kind, server_id, server_name);
-          write_config(config_path_string, &config_file, None);
+          write_config(config_path_string.clone(), &config_file, None);
+          used_config_path = config_path_string;
           break;
         }
       },
@@ -300,6 +365,7 @@ pub fn check_information(settings: &Settings) -> Option<HeadDict> {
         let (ipaddress, server_name) = configure_new_server(media_server_name);
         let user_login = configure_new_login(media_server_name);
         device_id = uuid::Uuid::new_v4().to_string();
+        used_config_path = config_path_string.clone();
         let auth = test_auth(media_server_name, media_server, &ipaddress, &auth_header, &user_login, &device_id);
         if let Some(pyld) = auth {
           auth_header = pyld.0;
@@ -347,19 +413,77 @@ pub fn check_information(settings: &Settings) -> Option<HeadDict> {
       username: user_login.username
     };
     let config_path_string = generate_config_path(server_kind, server_id, server_name);
-    write_config(config_path_string, &config_file, None);
+    write_config(config_path_string.clone(), &config_file, None);
+    used_config_path = config_path_string;
   }
-  Some(HeadDict {
+  let overrides: SettingsOverrides = read_overrides(&used_config_path);
+  if overrides.is_set() {
+    println!("Using the settings of this server: {}", overrides.to_string().green());
+    settings.apply_overrides(&overrides);
+  }
+  let mut head_dict = HeadDict {
     media_server_name: media_server_name.to_string(),
     media_server: media_server.to_string(),
     config_file,
     auth_header,
     request_header,
-    session_id
+    session_id,
+    config_path: used_config_path,
+    offline: false,
+    server_configuration: ServerConfiguration::default()
+  };
+  head_dict.server_configuration = server_configuration(&head_dict);
+  Some(head_dict)
+}
+
+
+// Used for playing downloaded items, when the server can't be reached.
+pub fn offline_head_dict(config_path_string: &String) -> Option<HeadDict> {
+  let (config_file, _raw) = read_config(config_path_string, true).ok()?;
+  let (media_server, media_server_name) = if config_file.emby {
+    ("/emby", "Emby")
+  } else {
+    ("", "Jellyfin")
+  };
+  Some(HeadDict {
+    media_server_name: media_server_name.to_string(),
+    media_server: media_server.to_string(),
+    auth_header: AuthHeader {
+      authorization: format!("Emby UserId={}, Client=\"Emby Theater\", Device=\"{}\", DeviceId=\"{}\", Version=\"{}\", Token={}", &config_file.user_id, APPNAME, &config_file.device_id, VERSION, &config_file.access_token)
+    },
+    request_header: get_request_header(&config_file.access_token),
+    session_id: String::new(),
+    config_path: config_path_string.clone(),
+    config_file,
+    offline: true,
+    server_configuration: ServerConfiguration::default()
   })
 }
 
 
+// Logs in without asking anything, so it fails if the session of the default user expired.
+pub fn login(config_path_string: &String) -> Option<HeadDict> {
+  let mut head_dict = offline_head_dict(config_path_string)?;
+  println!("Logging in with {} on {}.", head_dict.config_file.username.green(), head_dict.config_file.server_name.green());
+  match re_auth(&head_dict.media_server_name, &head_dict.media_server, &head_dict.config_file.ipaddress, &head_dict.auth_header, &head_dict.config_file.device_id) {
+    Ok(session_id) => {
+      head_dict.session_id = session_id;
+      head_dict.offline = false;
+      head_dict.server_configuration = server_configuration(&head_dict);
+      Some(head_dict)
+    },
+    Err(e) if e == "exp" => {
+      println!("{}", format!("Your {} session expired! Please re-login through the menu first.", head_dict.media_server_name).red());
+      None
+    },
+    Err(e) => {
+      println!("{}\n  Error: {}", "Failed to establish a working connection!".to_string().red(), e);
+      None
+    }
+  }
+}
+
+
 fn configure_new_server(media_server_name: &str) -> (String, String) {
   let mut ipaddress: String;
   let mut server_name: String;
@@ -0,0 +1,222 @@
+// Copies the watched state of one media-server to another, f.e. when moving from Emby to Jellyfin
+use std::collections::HashMap;
+use std::time::Duration;
+use colored::Colorize;
+use isahc::prelude::*;
+use isahc::Request;
+use serde_derive::Deserialize;
+use crate::config::choose_config;
+use crate::mediaserver_information::{getch, login, HeadDict};
+use crate::puddler_get;
+
+
+#[derive(Debug, Deserialize)]
+struct MigrationJson {
+    Items: Vec<MigrationItem>
+}
+
+
+#[derive(Debug, Deserialize)]
+struct MigrationItem {
+    Id: String,
+    Name: String,
+    Type: String,
+    #[serde(default)]
+    ProviderIds: HashMap<String, String>,
+    ProductionYear: Option<u32>,
+    SeriesName: Option<String>,
+    ParentIndexNumber: Option<u32>,
+    IndexNumber: Option<u32>,
+    UserData: MigrationUserData
+}
+
+
+#[derive(Debug, Deserialize)]
+struct MigrationUserData {
+    Played: bool,
+    PlaybackPositionTicks: i64,
+    IsFavorite: bool
+}
+
+
+enum Change {
+    Played,
+    Position(i64),
+    Favorite
+}
+
+
+impl MigrationItem {
+    fn title(&self) -> String {
+        match (&self.SeriesName, self.ParentIndexNumber, self.IndexNumber) {
+            (Some(series), Some(season), Some(episode)) => format!("{} S{:02}E{:02} - {}", series, season, episode, self.Name),
+            _ => format!("{} ({})", self.Name, self.ProductionYear.map(|year| year.to_string()).unwrap_or("????".to_string()))
+        }
+    }
+
+    // Provider ids are preferred, the name (and year or episode number) is only used if none of them match.
+    fn keys(&self) -> (Vec<String>, Option<String>) {
+        let mut providers: Vec<String> = self.ProviderIds.iter()
+            .filter(|(provider, _)| ["imdb", "tmdb", "tvdb"].contains(&provider.to_lowercase().as_str()))
+            .map(|(provider, id)| format!("{}:{}:{}", self.Type, provider.to_lowercase(), id.to_lowercase()))
+            .collect();
+        providers.sort();
+        let fallback = if self.Type == "Episode" {
+            match (&self.SeriesName, self.ParentIndexNumber, self.IndexNumber) {
+                (Some(series), Some(season), Some(episode)) => Some(format!("Episode:{}:{}:{}", series.to_lowercase(), season, episode)),
+                _ => None
+            }
+        } else {
+            self.ProductionYear.map(|year| format!("Movie:{}:{}", self.Name.to_lowercase(), year))
+        };
+        (providers, fallback)
+    }
+}
+
+
+fn choose_server(role: &str) -> Option<HeadDict> {
+    print!("Which kind of server is the {role}?\n   [1] Emby\n   [2] Jellyfin");
+    let server_kind = getch("12");
+    match choose_config(server_kind, false) {
+        Some(config_path) => login(&config_path),
+        None => {
+            println!("{}", "There is no configuration for this kind of server. Please log in through the menu first.".to_string().red());
+            None
+        }
+    }
+}
+
+
+fn fetch_items(head_dict: &HeadDict) -> Option<Vec<MigrationItem>> {
+    println!("Fetching the library of {} ...", head_dict.config_file.server_name);
+    let response = puddler_get(format!("{}{}/Users/{}/Items?Recursive=true&IncludeItemTypes=Movie,Episode&Fields=ProviderIds,ProductionYear&EnableUserData=true",
+        head_dict.config_file.ipaddress, head_dict.media_server, head_dict.config_file.user_id), head_dict);
+    match response {
+        Ok(mut t) => t.json::<MigrationJson>().ok().map(|json| json.Items),
+        Err(e) => {
+            println!("{}\n  Error: {}", "Couldn't fetch the library!".to_string().red(), e);
+            None
+        }
+    }
+}
+
+
+fn post(head_dict: &HeadDict, path: String, body: String) -> Result<(), String> {
+    let response = Request::post(format!("{}{}{}", head_dict.config_file.ipaddress, head_dict.media_server, path))
+        .timeout(Duration::from_secs(10))
+        .header("Authorization", &head_dict.auth_header.authorization)
+        .header("Content-Type", "application/json")
+        .body(body).map_err(|e| e.to_string())?
+        .send().map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(response.status().to_string())
+    }
+}
+
+
+// Nothing is ever removed from the target, only played flags, positions and favorites are added.
+fn changes(source: &MigrationItem, target: &MigrationItem) -> Vec<Change> {
+    let mut changes: Vec<Change> = Vec::new();
+    if source.UserData.Played && !target.UserData.Played {
+        changes.push(Change::Played);
+    } else if !source.UserData.Played && !target.UserData.Played && source.UserData.PlaybackPositionTicks > 0
+        && source.UserData.PlaybackPositionTicks != target.UserData.PlaybackPositionTicks {
+        changes.push(Change::Position(source.UserData.PlaybackPositionTicks));
+    }
+    if source.UserData.IsFavorite && !target.UserData.IsFavorite {
+        changes.push(Change::Favorite);
+    }
+    changes
+}
+
+
+fn format_ticks(ticks: i64) -> String {
+    let seconds = ticks / 10000000;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
+
+
+pub fn migrate_watched_state() {
+    let Some(source) = choose_server("source") else { return };
+    let Some(target) = choose_server("target") else { return };
+    if source.config_path == target.config_path {
+        println!("{}", "Source and target have to be different.".to_string().red());
+        return
+    }
+    let (Some(source_items), Some(target_items)) = (fetch_items(&source), fetch_items(&target)) else { return };
+
+    let mut by_provider: HashMap<String, usize> = HashMap::new();
+    let mut by_name: HashMap<String, usize> = HashMap::new();
+    for (index, item) in target_items.iter().enumerate() {
+        let (providers, fallback) = item.keys();
+        for key in providers {
+            by_provider.insert(key, index);
+        }
+        if let Some(key) = fallback {
+            by_name.insert(key, index);
+        }
+    }
+
+    let mut plan: Vec<(&MigrationItem, &MigrationItem, Vec<Change>)> = Vec::new();
+    let mut unmatched: usize = 0;
+    for item in &source_items {
+        if !item.UserData.Played && item.UserData.PlaybackPositionTicks == 0 && !item.UserData.IsFavorite {
+            continue
+        }
+        let (providers, fallback) = item.keys();
+        let matched = providers.iter().find_map(|key| by_provider.get(key))
+            .or(fallback.as_ref().and_then(|key| by_name.get(key)));
+        match matched {
+            Some(&index) => {
+                let changes = changes(item, &target_items[index]);
+                if !changes.is_empty() {
+                    plan.push((item, &target_items[index], changes));
+                }
+            },
+            None => unmatched += 1
+        }
+    }
+
+    if plan.is_empty() {
+        println!("\n{} is already up to date. ({} watched items couldn't be found on it)", target.config_file.server_name, unmatched);
+        return
+    }
+    println!("\nThe following changes would be made on {}:", target.config_file.server_name.cyan());
+    for (source_item, _, changes) in &plan {
+        let description: Vec<String> = changes.iter().map(|change| match change {
+            Change::Played => "[PLAYED]".to_string().green().to_string(),
+            Change::Position(ticks) => format!("resume at {}", format_ticks(*ticks)),
+            Change::Favorite => "[FAVORITE]".to_string().yellow().to_string()
+        }).collect();
+        println!("  + {} {}", source_item.title(), description.join(" "));
+    }
+    println!("\n{} item(s) will be updated, {} watched item(s) couldn't be found on the target.", plan.len(), unmatched);
+    print!("Do you want to apply these changes?\n (Y)es / (N)o");
+    if !"Yy".contains(getch("YyNn")) {
+        println!("Nothing has been changed.");
+        return
+    }
+
+    let user_id = &target.config_file.user_id;
+    let mut failed: usize = 0;
+    for (_, target_item, changes) in &plan {
+        for change in changes {
+            let result = match change {
+                Change::Played => post(&target, format!("/Users/{}/PlayedItems/{}", user_id, target_item.Id), "".to_string()),
+                Change::Position(ticks) => post(&target, format!("/Users/{}/Items/{}/UserData", user_id, target_item.Id), format!("{{\"PlaybackPositionTicks\":{ticks}}}")),
+                Change::Favorite => post(&target, format!("/Users/{}/FavoriteItems/{}", user_id, target_item.Id), "".to_string())
+            };
+            if let Err(e) = result {
+                println!("{}", format!("Couldn't update {}: {}", target_item.title(), e).red());
+                failed += 1;
+            }
+        }
+    }
+    if failed == 0 {
+        println!("{}", format!("Copied the watched state of {} item(s).", plan.len()).green());
+    } else {
+        println!("{}", format!("{failed} change(s) couldn't be applied.").red());
+    }
+}
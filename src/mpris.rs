@@ -0,0 +1,361 @@
+// Exposes the running playback as an MPRIS media player on the session bus (Linux only)
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use crate::mediaserver_information::HeadDict;
+use crate::player::PlaybackState;
+use crate::Items;
+
+
+// Requests coming from media keys or status bars, which are handled by the playback loop.
+#[derive(Debug, Clone, Copy)]
+pub enum MprisCommand {
+    PlayPause,
+    Play,
+    Pause,
+    Stop,
+    Seek(i64),
+    SetPosition(i64),
+    Next,
+    Previous
+}
+
+
+// The range of mpv's "speed" property.
+const MINIMUM_RATE: f64 = 0.01;
+const MAXIMUM_RATE: f64 = 100.0;
+
+
+struct Shared {
+    playing: bool,
+    position: i64,
+    rate: f64,
+    track_id: String,
+    title: String,
+    album: Option<String>,
+    artist: Option<String>,
+    art_url: String,
+    length: Option<i64>,
+    can_go_next: bool,
+    can_go_previous: bool
+}
+
+
+pub struct Mpris {
+    shared: Arc<Mutex<Shared>>,
+    receiver: Receiver<MprisCommand>,
+    #[cfg(target_os = "linux")]
+    connection: zbus::blocking::Connection
+}
+
+
+impl Mpris {
+    pub fn update(&self, state: &PlaybackState) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.position = (state.position * 1000000.0) as i64;
+        if shared.playing == state.paused {
+            shared.playing = !state.paused;
+            #[cfg(target_os = "linux")]
+            dbus::playback_status_changed(&self.connection, shared.playing);
+        }
+        if shared.rate != state.speed {
+            shared.rate = state.speed;
+            #[cfg(target_os = "linux")]
+            dbus::rate_changed(&self.connection, shared.rate);
+        }
+    }
+
+    // Clients only ask for the position now and then, so they have to be told about jumps.
+    pub fn seeked(&self, position: f64) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.position = (position * 1000000.0) as i64;
+        #[cfg(target_os = "linux")]
+        dbus::seeked(&self.connection, shared.position);
+    }
+
+    pub fn commands(&self) -> Vec<MprisCommand> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+
+// Positions and lengths are in microseconds, as MPRIS expects them.
+pub fn start(head_dict: &HeadDict, item: &Items, can_go_next: bool, can_go_previous: bool) -> Option<Mpris> {
+    let shared = Arc::new(Mutex::new(Shared {
+        playing: true,
+        position: item.UserData.PlaybackPositionTicks / 10,
+        rate: 1.0,
+        track_id: format!("/org/puddler/track/{}", item.Id.replace('-', "")),
+        title: item.Name.clone(),
+        album: item.SeasonName.clone(),
+        artist: item.SeriesName.clone(),
+        art_url: format!("{}{}/Items/{}/Images/Primary", head_dict.config_file.ipaddress, head_dict.media_server, item.SeriesId.as_ref().unwrap_or(&item.Id)),
+        length: item.RunTimeTicks.map(|ticks| (ticks / 10) as i64),
+        can_go_next,
+        can_go_previous
+    }));
+    let (sender, receiver) = channel();
+    start_server(shared.clone(), sender).map(|_connection| Mpris {
+        shared,
+        receiver,
+        #[cfg(target_os = "linux")]
+        connection: _connection
+    })
+}
+
+
+#[cfg(target_os = "linux")]
+fn start_server(shared: Arc<Mutex<Shared>>, sender: Sender<MprisCommand>) -> Option<zbus::blocking::Connection> {
+    match dbus::connect(shared, sender) {
+        Ok(connection) => Some(connection),
+        Err(e) => {
+            println!("Couldn't register the MPRIS interface: {e}");
+            None
+        }
+    }
+}
+
+
+#[cfg(not(target_os = "linux"))]
+fn start_server(_shared: Arc<Mutex<Shared>>, _sender: Sender<MprisCommand>) -> Option<()> {
+    None
+}
+
+
+#[cfg(target_os = "linux")]
+mod dbus {
+    use std::collections::HashMap;
+    use std::sync::mpsc::Sender;
+    use std::sync::{Arc, Mutex};
+    use zbus::blocking::connection::Builder;
+    use zbus::blocking::Connection;
+    use zbus::interface;
+    use zbus::zvariant::{ObjectPath, Value};
+    use super::{MprisCommand, Shared, MAXIMUM_RATE, MINIMUM_RATE};
+    use crate::APPNAME;
+
+    const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+
+    struct Root {
+        commands: Mutex<Sender<MprisCommand>>
+    }
+
+
+    #[interface(name = "org.mpris.MediaPlayer2")]
+    impl Root {
+        fn raise(&self) {}
+
+        fn quit(&self) {
+            self.commands.lock().unwrap().send(MprisCommand::Stop).ok();
+        }
+
+        #[zbus(property)]
+        fn can_quit(&self) -> bool {
+            true
+        }
+
+        #[zbus(property)]
+        fn can_raise(&self) -> bool {
+            false
+        }
+
+        #[zbus(property)]
+        fn has_track_list(&self) -> bool {
+            false
+        }
+
+        #[zbus(property)]
+        fn identity(&self) -> String {
+            APPNAME.to_string()
+        }
+
+        #[zbus(property)]
+        fn supported_uri_schemes(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        #[zbus(property)]
+        fn supported_mime_types(&self) -> Vec<String> {
+            Vec::new()
+        }
+    }
+
+
+    struct Player {
+        shared: Arc<Mutex<Shared>>,
+        commands: Mutex<Sender<MprisCommand>>
+    }
+
+
+    impl Player {
+        fn send(&self, command: MprisCommand) {
+            self.commands.lock().unwrap().send(command).ok();
+        }
+    }
+
+
+    #[interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl Player {
+        fn next(&self) {
+            self.send(MprisCommand::Next);
+        }
+
+        fn previous(&self) {
+            self.send(MprisCommand::Previous);
+        }
+
+        fn pause(&self) {
+            self.send(MprisCommand::Pause);
+        }
+
+        fn play_pause(&self) {
+            self.send(MprisCommand::PlayPause);
+        }
+
+        fn stop(&self) {
+            self.send(MprisCommand::Stop);
+        }
+
+        fn play(&self) {
+            self.send(MprisCommand::Play);
+        }
+
+        fn seek(&self, offset: i64) {
+            self.send(MprisCommand::Seek(offset));
+        }
+
+        fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+            if track_id.as_str() == self.shared.lock().unwrap().track_id {
+                self.send(MprisCommand::SetPosition(position));
+            }
+        }
+
+        fn open_uri(&self, _uri: String) {}
+
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            playback_status(self.shared.lock().unwrap().playing)
+        }
+
+        #[zbus(property)]
+        fn rate(&self) -> f64 {
+            self.shared.lock().unwrap().rate
+        }
+
+        #[zbus(property)]
+        fn minimum_rate(&self) -> f64 {
+            MINIMUM_RATE
+        }
+
+        #[zbus(property)]
+        fn maximum_rate(&self) -> f64 {
+            MAXIMUM_RATE
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, Value<'static>> {
+            let shared = self.shared.lock().unwrap();
+            let mut metadata: HashMap<String, Value<'static>> = HashMap::new();
+            if let Ok(track_id) = ObjectPath::try_from(shared.track_id.clone()) {
+                metadata.insert("mpris:trackid".to_string(), Value::from(track_id));
+            }
+            metadata.insert("xesam:title".to_string(), Value::from(shared.title.clone()));
+            metadata.insert("mpris:artUrl".to_string(), Value::from(shared.art_url.clone()));
+            if let Some(length) = shared.length {
+                metadata.insert("mpris:length".to_string(), Value::from(length));
+            }
+            if let Some(album) = &shared.album {
+                metadata.insert("xesam:album".to_string(), Value::from(album.clone()));
+            }
+            if let Some(artist) = &shared.artist {
+                metadata.insert("xesam:artist".to_string(), Value::from([artist.clone()].to_vec()));
+            }
+            metadata
+        }
+
+        #[zbus(property(emits_changed_signal = "false"))]
+        fn position(&self) -> i64 {
+            self.shared.lock().unwrap().position
+        }
+
+        #[zbus(property)]
+        fn can_go_next(&self) -> bool {
+            self.shared.lock().unwrap().can_go_next
+        }
+
+        #[zbus(property)]
+        fn can_go_previous(&self) -> bool {
+            self.shared.lock().unwrap().can_go_previous
+        }
+
+        #[zbus(property)]
+        fn can_play(&self) -> bool {
+            true
+        }
+
+        #[zbus(property)]
+        fn can_pause(&self) -> bool {
+            true
+        }
+
+        #[zbus(property)]
+        fn can_seek(&self) -> bool {
+            true
+        }
+
+        #[zbus(property)]
+        fn can_control(&self) -> bool {
+            true
+        }
+    }
+
+
+    fn playback_status(playing: bool) -> String {
+        if playing {
+            "Playing".to_string()
+        } else {
+            "Paused".to_string()
+        }
+    }
+
+
+    // Every running instance gets its own bus name, so multiple players don't collide.
+    pub fn connect(shared: Arc<Mutex<Shared>>, sender: Sender<MprisCommand>) -> zbus::Result<Connection> {
+        Builder::session()?
+            .name(format!("org.mpris.MediaPlayer2.puddler.instance{}", std::process::id()))?
+            .serve_at(OBJECT_PATH, Root {
+                commands: Mutex::new(sender.clone())
+            })?
+            .serve_at(OBJECT_PATH, Player {
+                shared,
+                commands: Mutex::new(sender)
+            })?
+            .build()
+    }
+
+
+    fn properties_changed(connection: &Connection, changed: HashMap<&str, Value>) {
+        connection.emit_signal(
+            None::<()>,
+            OBJECT_PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            &("org.mpris.MediaPlayer2.Player", changed, Vec::<String>::new())
+        ).ok();
+    }
+
+
+    pub fn playback_status_changed(connection: &Connection, playing: bool) {
+        properties_changed(connection, HashMap::from([("PlaybackStatus", Value::from(playback_status(playing)))]));
+    }
+
+
+    pub fn rate_changed(connection: &Connection, rate: f64) {
+        properties_changed(connection, HashMap::from([("Rate", Value::from(rate))]));
+    }
+
+
+    pub fn seeked(connection: &Connection, position: i64) {
+        connection.emit_signal(None::<()>, OBJECT_PATH, "org.mpris.MediaPlayer2.Player", "Seeked", &position).ok();
+    }
+}
@@ -0,0 +1,125 @@
+// Talks to an external mpv instance through its JSON IPC protocol (--input-ipc-server)
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+
+#[cfg(unix)]
+type IpcStream = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+type IpcStream = std::fs::File;
+
+
+pub struct MpvIpc {
+    reader: BufReader<IpcStream>,
+    writer: IpcStream,
+    request_id: u64,
+    pub events: VecDeque<Value>,
+}
+
+
+// Unix sockets live in the temp directory, Windows uses named pipes.
+pub fn ipc_path(name: &str) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(format!(r"\\.\pipe\{name}"))
+    } else {
+        std::env::temp_dir().join(format!("{name}.sock"))
+    }
+}
+
+
+#[cfg(unix)]
+fn open_stream(path: &PathBuf) -> std::io::Result<IpcStream> {
+    std::os::unix::net::UnixStream::connect(path)
+}
+
+
+#[cfg(windows)]
+fn open_stream(path: &PathBuf) -> std::io::Result<IpcStream> {
+    std::fs::OpenOptions::new().read(true).write(true).open(path)
+}
+
+
+impl MpvIpc {
+    // mpv needs a moment to create the socket after being spawned.
+    pub fn connect(path: &PathBuf, timeout: Duration) -> Result<MpvIpc, String> {
+        let start = Instant::now();
+        loop {
+            match open_stream(path) {
+                Ok(stream) => {
+                    let writer = stream.try_clone().map_err(|e| e.to_string())?;
+                    return Ok(MpvIpc {
+                        reader: BufReader::new(stream),
+                        writer,
+                        request_id: 0,
+                        events: VecDeque::new(),
+                    });
+                }
+                Err(e) => {
+                    if start.elapsed() > timeout {
+                        return Err(format!("Couldn't connect to mpv ({}): {e}", path.display()));
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+        }
+    }
+
+    pub fn command(&mut self, args: Value) -> Result<Value, String> {
+        self.request_id += 1;
+        let request = json!({ "command": args, "request_id": self.request_id });
+        writeln!(self.writer, "{request}").map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())?;
+        loop {
+            let message = self.read_message()?;
+            if message.get("event").is_some() {
+                self.events.push_back(message);
+            } else if message["request_id"].as_u64() == Some(self.request_id) {
+                return match message["error"].as_str() {
+                    Some("success") => Ok(message["data"].clone()),
+                    Some(error) => Err(error.to_string()),
+                    None => Err("invalid response".to_string()),
+                };
+            }
+        }
+    }
+
+    pub fn set_property(&mut self, name: &str, value: Value) -> Result<(), String> {
+        self.command(json!(["set_property", name, value]))?;
+        Ok(())
+    }
+
+    pub fn get_property(&mut self, name: &str) -> Result<Value, String> {
+        self.command(json!(["get_property", name]))
+    }
+
+    // Events only arrive in between replies, so a cheap request is sent to collect them.
+    // This works the same for unix sockets and named pipes, which can't be read with a timeout.
+    pub fn next_event(&mut self, timeout: Duration) -> Result<Option<Value>, String> {
+        if self.events.is_empty() {
+            thread::sleep(timeout);
+            if let Err(e) = self.command(json!(["get_property", "pid"])) {
+                if self.events.is_empty() {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.events.pop_front())
+    }
+
+    fn read_message(&mut self) -> Result<Value, String> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = self.reader.read_line(&mut line).map_err(|e| e.to_string())?;
+            if read == 0 {
+                return Err("mpv closed the connection".to_string());
+            }
+            if let Ok(message) = serde_json::from_str::<Value>(&line) {
+                return Ok(message);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// Extra mpv options from the settings, both global ones and profiles, which are chosen per library, series or item type
+use std::collections::BTreeMap;
+use colored::Colorize;
+use isahc::ReadResponseExt;
+use serde_derive::{Deserialize, Serialize};
+use crate::backend::Player;
+use crate::mediaserver_information::HeadDict;
+use crate::settings::Settings;
+use crate::{puddler_get, Items};
+
+
+// A profile is used for every item, which matches any of its libraries, series or item types (f.e. "Movie", "Episode").
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct MpvProfile {
+    pub libraries: Vec<String>,
+    pub series: Vec<String>,
+    pub types: Vec<String>,
+    pub options: BTreeMap<String, String>
+}
+
+
+impl MpvProfile {
+    fn matches(&self, item: &Items, library: Option<&str>) -> bool {
+        let contains = |list: &[String], value: &str| list.iter().any(|entry| entry.eq_ignore_ascii_case(value));
+        contains(&self.types, &item.Type)
+            || item.SeriesName.as_ref().is_some_and(|series| contains(&self.series, series))
+            || library.is_some_and(|library| contains(&self.libraries, library))
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+struct Ancestor {
+    Name: String,
+    Type: String
+}
+
+
+fn library(head_dict: &HeadDict, item: &Items) -> Option<String> {
+    let mut response = puddler_get(format!("{}{}/Items/{}/Ancestors?UserId={}", head_dict.config_file.ipaddress, head_dict.media_server, item.Id, head_dict.config_file.user_id), head_dict).ok()?;
+    let ancestors: Vec<Ancestor> = serde_json::from_str(&response.text().ok()?).ok()?;
+    ancestors.into_iter().find(|ancestor| ancestor.Type == "CollectionFolder").map(|ancestor| ancestor.Name)
+}
+
+
+// The global options come first, so profiles can override them. Profiles are applied in alphabetical order.
+pub fn options_for(settings: &Settings, head_dict: &HeadDict, item: &Items) -> Vec<(String, String)> {
+    // The library has to be requested, so it's only looked up if a profile needs it.
+    let library: Option<String> = if settings.mpv_profiles.values().any(|profile| !profile.libraries.is_empty()) {
+        library(head_dict, item)
+    } else {
+        None
+    };
+    let mut options: Vec<(String, String)> = settings.mpv_options.clone().into_iter().collect();
+    for (name, profile) in &settings.mpv_profiles {
+        if profile.matches(item, library.as_deref()) {
+            println!("Using the mpv profile \"{name}\".");
+            options.extend(profile.options.clone());
+        }
+    }
+    options
+}
+
+
+// Values are prefixed with their length, so they can contain commas.
+fn per_file(options: &[(String, String)]) -> String {
+    options.iter().map(|(name, value)| format!("{name}=%{}%{value}", value.len())).collect::<Vec<String>>().join(",")
+}
+
+
+// The options are passed as per-file options, so mpv resets them once the item ends (f.e. when binge mode reuses the player).
+// "profile" refers to a profile of mpv.conf, mpv applies it only for this file as well.
+pub fn loadfile(mpv: &mut dyn Player, url: &str, options: &[(String, String)]) -> Result<(), String> {
+    if options.is_empty() {
+        return mpv.command(&["loadfile", url])
+    }
+    let per_file: String = per_file(options);
+    // mpv 0.38 added the playlist index in front of the options.
+    let loaded = mpv.command(&["loadfile", url, "replace", "-1", &per_file])
+        .or_else(|_| mpv.command(&["loadfile", url, "replace", &per_file]));
+    if let Err(e) = loaded {
+        println!("{}", format!("Couldn't use the mpv options \"{per_file}\": {e}").red());
+        return mpv.command(&["loadfile", url])
+    }
+    Ok(())
+}
@@ -1,31 +1,149 @@
-extern crate mpv;
 use std::io;
 use std::io::prelude::*;
 use colored::Colorize;
-use mpv::MpvHandler;
 use serde_derive::{Deserialize};
 use serde::Serialize;
 use isahc::ReadResponseExt;
 use crate::getch;
-use crate::discord;
-use crate::discord::DiscordClient;
+use crate::backend;
+use crate::binge;
+use crate::backend::{Player, PlayerEvent, PropertyFormat, PropertyValue};
+use crate::presence;
+use crate::presence::{Presence, PresenceBackend};
 use crate::APPNAME;
 use crate::Items;
 use crate::mediaserver_information::HeadDict;
 use crate::mediaserver_information::post_puddler;
 use crate::progress_report::MediaStream;
+use crate::progress_report::MediaSourceInfo;
 use crate::puddler_get;
-use crate::is_numeric;
+use crate::bandwidth::{get_bitrate, remeasure_bitrate};
+use crate::downloads;
+use crate::keybindings::{Action, Keybindings};
+use crate::mpris;
+use crate::mpv_options;
+use crate::mpris::{Mpris, MprisCommand};
+use crate::hooks::{run_hook, HookEvent};
+use crate::downloads::DownloadedItem;
 use crate::settings::Settings;
 use crate::progress_report::PlaybackInfo;
 use crate::progress_report::finished_playback;
 use crate::progress_report::update_progress;
 use crate::progress_report::started_playing;
-use std::{thread, time};
-use std::time::SystemTime;
+use crate::progress_report::PlaySession;
+use std::time;
 use dialoguer::{theme::ColorfulTheme, Select};
 
 
+// The index of each property is used as its reply_userdata.
+const OBSERVED_PROPERTIES: [(&str, PropertyFormat); 9] = [
+	("time-pos", PropertyFormat::Double),
+	("pause", PropertyFormat::Flag),
+	("aid", PropertyFormat::Str),
+	("sid", PropertyFormat::Str),
+	("volume", PropertyFormat::Double),
+	("mute", PropertyFormat::Flag),
+	("speed", PropertyFormat::Double),
+	("paused-for-cache", PropertyFormat::Flag),
+	("loop-file", PropertyFormat::Str),
+];
+
+
+// How a playback ended, so the caller knows whether to continue with another item of the queue.
+pub enum PlaybackEnd {
+	Finished,
+	Next,
+	Previous,
+	// The player has been closed, instead of only the item ending.
+	Closed
+}
+
+
+#[derive(Debug, Clone)]
+pub struct PlaybackState {
+	pub position: f64,
+	pub paused: bool,
+	pub audio_track: Option<i64>,
+	pub subtitle_track: Option<i64>,
+	pub audio_stream_index: Option<usize>,
+	pub subtitle_stream_index: Option<usize>,
+	pub volume: f64,
+	pub muted: bool,
+	pub speed: f64,
+	pub repeat_mode: &'static str,
+	pub buffering: bool
+}
+
+
+impl Default for PlaybackState {
+	fn default() -> Self {
+		Self {
+			position: 0.0,
+			paused: false,
+			audio_track: None,
+			subtitle_track: None,
+			audio_stream_index: None,
+			subtitle_stream_index: None,
+			volume: 100.0,
+			muted: false,
+			speed: 1.0,
+			repeat_mode: "RepeatNone",
+			buffering: false
+		}
+	}
+}
+
+
+impl PlaybackState {
+	// Returns the event name, if the change should be reported to the server right away.
+	fn apply(&mut self, name: &str, change: PropertyValue) -> Option<&'static str> {
+		match (name, change) {
+			("time-pos", PropertyValue::Double(position)) => {
+				self.position = position;
+				None
+			},
+			("pause", PropertyValue::Flag(paused)) if paused != self.paused => {
+				self.paused = paused;
+				Some(if paused { "Pause" } else { "Unpause" })
+			},
+			("aid", PropertyValue::Str(track)) if track.parse::<i64>().ok() != self.audio_track => {
+				self.audio_track = track.parse::<i64>().ok();
+				Some("AudioTrackChange")
+			},
+			("sid", PropertyValue::Str(track)) if track.parse::<i64>().ok() != self.subtitle_track => {
+				self.subtitle_track = track.parse::<i64>().ok();
+				Some("SubtitleTrackChange")
+			},
+			("volume", PropertyValue::Double(volume)) if volume != self.volume => {
+				self.volume = volume;
+				Some("VolumeChange")
+			},
+			("mute", PropertyValue::Flag(muted)) if muted != self.muted => {
+				self.muted = muted;
+				Some("VolumeChange")
+			},
+			("speed", PropertyValue::Double(speed)) if speed != self.speed => {
+				self.speed = speed;
+				Some("TimeUpdate")
+			},
+			("paused-for-cache", PropertyValue::Flag(buffering)) => {
+				self.buffering = buffering;
+				None
+			},
+			("loop-file", PropertyValue::Str(looping)) => {
+				let repeat_mode = if looping == "no" { "RepeatNone" } else { "RepeatOne" };
+				if repeat_mode == self.repeat_mode {
+					return None
+				}
+				self.repeat_mode = repeat_mode;
+				Some("RepeatModeChange")
+			},
+			_ => None
+		}
+	}
+}
+
+
 #[derive(Debug, Serialize, Deserialize)]
 struct SessionCapabilities {
 	UserId: String,
@@ -63,9 +181,13 @@ struct DirectPlayProfile {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct TranscodingProfile {
 	Type: String,
+	Context: String,
+	Protocol: String,
 	TranscodeSeekInfo: String,
 	VideoCodec: String,
-	Container: String
+	AudioCodec: String,
+	Container: String,
+	BreakOnNonKeyFrames: bool
 }
 
 
@@ -76,7 +198,59 @@ struct SubtitleProfile {
 }
 
 
-fn choose_trackIndexx(item: &Items) -> (usize, usize) {
+// None, if the item has nothing to play or the user didn't choose anything.
+pub fn choose_media_source(settings: &Settings, item: &Items) -> Option<MediaSourceInfo> {
+	let media_sources: &Vec<MediaSourceInfo> = match item.MediaSources.as_ref() {
+		Some(media_sources) if !media_sources.is_empty() => media_sources,
+		_ => {
+			println!("{}", format!("{} doesn't have any playable version.", item.Name).red());
+			return None
+		}
+	};
+	if media_sources.len() == 1 {
+		return Some(media_sources[0].clone());
+	}
+	let height = |source: &MediaSourceInfo| source.video_stream().and_then(|v| v.Height).unwrap_or(0);
+	let chosen: Option<&MediaSourceInfo> = match settings.version_preference.as_str() {
+		"1080p" => {
+			media_sources.iter().filter(|source| height(source) <= 1080).max_by_key(|source| height(source))
+				.or(media_sources.iter().min_by_key(|source| height(source)))
+		},
+		"hdr" => {
+			// Without a HDR version, the best looking one is used.
+			media_sources.iter().filter(|source| source.video_stream().and_then(|v| v.VideoRange.as_ref()).is_some_and(|range| range != "SDR"))
+				.max_by_key(|source| source.Bitrate.unwrap_or(0))
+				.or(media_sources.iter().max_by_key(|source| source.Bitrate.unwrap_or(0)))
+		},
+		"smallest" => {
+			// Sizes and bitrates can't be compared with each other, the bitrate is only used if no version has a size.
+			if media_sources.iter().any(|source| source.Size.is_some()) {
+				media_sources.iter().min_by_key(|source| source.Size.unwrap_or(u64::MAX))
+			} else {
+				media_sources.iter().min_by_key(|source| source.Bitrate.unwrap_or(u64::MAX))
+			}
+		},
+		_ => None
+	};
+	match chosen {
+		Some(source) => {
+			println!("The following version will be used:\n{}", source.to_string().green());
+			Some(source.clone())
+		},
+		None => {
+			Select::with_theme(&ColorfulTheme::default())
+			.with_prompt("Please select which version you want to play:")
+			.default(0)
+			.items(&media_sources[..])
+			.interact()
+			.ok()
+			.map(|index| media_sources[index].clone())
+		}
+	}
+}
+
+
+pub fn choose_trackIndexx(media_source: &MediaSourceInfo) -> (usize, usize) {
 	fn select_ind(tracks: Vec<MediaStream>, kind: &str) -> usize {
 		match tracks.len() {
 			n if n > 1 => {
@@ -99,7 +273,7 @@ fn choose_trackIndexx(item: &Items) -> (usize, usize) {
 	}
 	let mut subtitle_tracks: Vec<MediaStream> = [].to_vec();
 	let mut audio_tracks: Vec<MediaStream> = [].to_vec();
-	let mediaStreams: &Vec<MediaStream> = &item.MediaSources.as_ref().unwrap().first().unwrap().MediaStreams;
+	let mediaStreams: &Vec<MediaStream> = &media_source.MediaStreams;
 	for track in mediaStreams.iter() {
 		match &track.Type as &str {
 			"Audio" => audio_tracks.append(&mut [track.clone()].to_vec()),
@@ -112,7 +286,31 @@ fn choose_trackIndexx(item: &Items) -> (usize, usize) {
 }
 
 
-pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items) {
+pub fn auth_header_field(head_dict: &HeadDict) -> String {
+	format!("X-Emby-Token: {}", head_dict.request_header.token)
+}
+
+
+// Servers include the token in the TranscodingUrl, which would end up in logs and process lists.
+pub fn strip_api_key(url: &str) -> String {
+	match url.split_once('?') {
+		Some((path, query)) => {
+			let query: Vec<&str> = query.split('&').filter(|param| ! param.to_lowercase().starts_with("api_key=")).collect();
+			format!("{}?{}", path, query.join("&"))
+		},
+		None => url.to_string()
+	}
+}
+
+
+pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items, queue: &[Items]) -> PlaybackEnd {
+	play_in(settings, head_dict, Item, queue, &mut None)
+}
+
+
+// Plays the item in the given player, which is started if there is none yet.
+// mpv stays open afterwards, so the next item can be loaded into the same window.
+pub fn play_in(settings: &Settings, head_dict: &HeadDict, Item: &Items, queue: &[Items], player: &mut Option<Box<dyn Player>>) -> PlaybackEnd {
 	let item: &mut Items = &mut Item.clone();
 	item.UserData.PlaybackPositionTicks = {
 		if item.UserData.PlaybackPositionTicks == 0 && ! settings.transcoding {
@@ -159,30 +357,49 @@ pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items) {
 		}
 	};
 	
-	let playback_info: PlaybackInfo = if settings.transcoding {
-		let (audioIndex, subIndex) = choose_trackIndexx(item);
-		
-		print!("\nPlease enter your connection speed in mbps: ");
-		let mut mbps: String = String::new();
-		loop {
-			io::stdout().flush().expect("Failed to flush stdout");
-			io::stdin().read_line(&mut mbps).unwrap();
-			if ! is_numeric(mbps.trim()) {
-				print!("\nInvalid input! Enter something like \"25\" equal to ~3MB/s.\n: ")
-			} else {
-				break
-			}
-		};
+	// Downloaded items are always played from disk, even when the server is reachable.
+	let download: Option<DownloadedItem> = downloads::find_download(settings, &item.Id);
+	let transcoding: bool = settings.transcoding && download.is_none();
+
+	let media_source: Option<MediaSourceInfo> = if download.is_some() {
+		item.MediaSources.as_ref().and_then(|media_sources| media_sources.first()).cloned()
+	} else {
+		choose_media_source(settings, item)
+	};
+	let Some(media_source) = media_source else {
+		return PlaybackEnd::Finished
+	};
+
+	let mut state: PlaybackState = PlaybackState {
+		position: (item.UserData.PlaybackPositionTicks / 10000000) as f64,
+		..Default::default()
+	};
+
+	let mut bitrate: Option<u64> = download.as_ref().and_then(|downloaded| downloaded.bitrate).or(media_source.Bitrate);
+
+	let playback_info: PlaybackInfo = if download.is_some() {
+		// There might not be a server to ask, so the play session is made up locally.
+		PlaybackInfo {
+			MediaSources: [media_source.clone()].to_vec(),
+			PlaySessionId: uuid::Uuid::new_v4().simple().to_string()
+		}
+	} else if transcoding {
+		let (audioIndex, subIndex) = choose_trackIndexx(&media_source);
+		// The transcode only contains the chosen streams, so these stay the same for the whole session.
+		state.audio_stream_index = Some(audioIndex);
+		state.subtitle_stream_index = Some(subIndex);
 
-		let bitrate = mbps.trim().parse::<u64>().unwrap() * 1000000;
+		let max_bitrate = get_bitrate(head_dict);
+		bitrate = Some(max_bitrate);
 		let sess: SessionCapabilities = SessionCapabilities {
 			UserId: head_dict.config_file.user_id.clone(),
-			StartTimeTicks: item.UserData.PlaybackPositionTicks,
-			MediaSourceId: item.MediaSources.as_ref().unwrap()[0].Id.clone(),
+			// The HLS playlist always covers the whole item, the resume position is handled by mpv.
+			StartTimeTicks: 0,
+			MediaSourceId: media_source.Id.clone(),
 			AudioStreamIndex: audioIndex,
 			SubtitleStreamIndex: subIndex,
-			MaxStaticBitrate: bitrate,
-			MaxStreamingBitrate: bitrate,
+			MaxStaticBitrate: max_bitrate,
+			MaxStreamingBitrate: max_bitrate,
 			EnableDirectPlay: false,
 			EnableDirectStream: false,
 			EnableTranscoding: true,
@@ -192,53 +409,56 @@ pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items) {
 				Name: "mpv".to_string(),
 				Id: head_dict.config_file.device_id.clone(),
 				MaxStaticMusicBitrate: 999999999,
-				MaxStreamingBitrate: bitrate,
+				MaxStreamingBitrate: max_bitrate,
 				TranscodingProfiles: [
 					TranscodingProfile {
 						Type: "Video".to_string(),
-						Container: "mkv".to_string(),
-						VideoCodec: "hevc".to_string(),
-						TranscodeSeekInfo: "Auto".to_string(),
-					},
-					TranscodingProfile {
-						Type: "Video".to_string(),
-						Container: "mkv".to_string(),
-						VideoCodec: "avc".to_string(),
+						Context: "Streaming".to_string(),
+						Protocol: "hls".to_string(),
+						// Jellyfin only delivers hevc inside of fmp4 segments.
+						Container: if head_dict.media_server_name == "Emby" {
+							"ts".to_string()
+						} else {
+							"mp4".to_string()
+						},
+						VideoCodec: "hevc,h264".to_string(),
+						AudioCodec: "aac,mp3,ac3,eac3,opus".to_string(),
 						TranscodeSeekInfo: "Auto".to_string(),
+						BreakOnNonKeyFrames: true
 					}
 				].to_vec(),
 				SubtitleProfiles: [
 					SubtitleProfile {
 						Format: "subrip".to_string(),
-						Method: "Embed".to_string()
+						Method: "Hls".to_string()
 					},
 					SubtitleProfile {
 						Format: "srt".to_string(),
-						Method: "Embed".to_string()
+						Method: "Hls".to_string()
 					},
 					SubtitleProfile {
 						Format: "ass".to_string(),
-						Method: "Embed".to_string()
+						Method: "Hls".to_string()
 					},
 					SubtitleProfile {
 						Format: "ssa".to_string(),
-						Method: "Embed".to_string()
+						Method: "Hls".to_string()
 					},
 					SubtitleProfile {
 						Format: "pgssub".to_string(),
-						Method: "Embed".to_string()
+						Method: "Encode".to_string()
 					},
 					SubtitleProfile {
 						Format: "sub".to_string(),
-						Method: "Embed".to_string()
+						Method: "Encode".to_string()
 					},
 					SubtitleProfile {
 						Format: "dvdsub".to_string(),
-						Method: "Embed".to_string()
+						Method: "Encode".to_string()
 					},
 					SubtitleProfile {
 						Format: "pgs".to_string(),
-						Method: "Embed".to_string()
+						Method: "Encode".to_string()
 					}
 				].to_vec()
 			}
@@ -253,7 +473,7 @@ pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items) {
 		};
 		playback_info
 	} else {
-		let playback_info_res: Result<http::Response<isahc::Body>, isahc::Error> = puddler_get(format!("{}{}/Items/{}/PlaybackInfo?UserId={}", head_dict.config_file.ipaddress, head_dict.media_server, item.Id, head_dict.config_file.user_id), head_dict);
+		let playback_info_res: Result<http::Response<isahc::Body>, isahc::Error> = puddler_get(format!("{}{}/Items/{}/PlaybackInfo?UserId={}&MediaSourceId={}", head_dict.config_file.ipaddress, head_dict.media_server, item.Id, head_dict.config_file.user_id, media_source.Id), head_dict);
 		let playback_info: PlaybackInfo = match playback_info_res {
 			Ok(mut t) => {
 				let search_text: &String = &t.text().unwrap();
@@ -264,29 +484,41 @@ pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items) {
 		playback_info
 	};
 
-	started_playing(settings, head_dict, item, &playback_info);
+	let session: PlaySession = PlaySession {
+		item,
+		queue,
+		playsession_id: playback_info.PlaySessionId.clone(),
+		mediasource_id: media_source.Id.clone(),
+		transcoding,
+		bitrate
+	};
 
 	let resume_progress = item.UserData.PlaybackPositionTicks / 10000000;
 
-	let total_runtime: f64 = if settings.transcoding {
-		(item.RunTimeTicks.unwrap() as f64 - item.UserData.PlaybackPositionTicks as f64) / 10000000.0
+	let stream_url: String = if let Some(downloaded) = &download {
+		downloaded.file.clone()
+	} else if transcoding {
+		let transcoding_source: &MediaSourceInfo = playback_info.MediaSources.iter().find(|source| source.Id == media_source.Id).unwrap_or(&playback_info.MediaSources[0]);
+		strip_api_key(&format!("{}{}{}", head_dict.config_file.ipaddress, head_dict.media_server, transcoding_source.TranscodingUrl.as_ref().unwrap()))
 	} else {
-		item.RunTimeTicks.unwrap() as f64 / 10000000.0
+		format!("{}{}/Videos/{}/stream?Container=mkv&Static=true&MediaSourceId={}",
+    head_dict.config_file.ipaddress, head_dict.media_server, item.Id, media_source.Id)
 	};
 
-	let mut mpv_handle: mpv::MpvHandlerBuilder = mpv::MpvHandlerBuilder::new().expect("Failed to create MPV builder.");
-	mpv_handle.set_option("osc", true).unwrap();
-	mpv_handle.set_option("input-default-bindings", true).unwrap();
-	mpv_handle.set_option("input-vo-keyboard", true).unwrap();
-
-	let mut mpv: MpvHandler = mpv_handle.build().expect("Failed to create specified mpv configuration.");
-	
-	let stream_url: String = if settings.transcoding {
-		format!("{}{}{}", head_dict.config_file.ipaddress, head_dict.media_server, playback_info.MediaSources.get(0).unwrap().TranscodingUrl.as_ref().unwrap())
-	} else {
-		format!("{}{}/Videos/{}/stream?Container=mkv&Static=true&api_key={}",
-    head_dict.config_file.ipaddress, head_dict.media_server, item.Id, head_dict.request_header.token)
-	};
+	if player.is_none() {
+		let mut started: Box<dyn Player> = match backend::start(settings) {
+			Ok(player) => player,
+			Err(e) => {
+				println!("{}", format!("Couldn't start mpv: {e}").red());
+				return PlaybackEnd::Finished
+			}
+		};
+		observe(started.as_mut());
+		started.set_property("idle", "yes").ok();
+		started.set_property("force-window", "yes").ok();
+		*player = Some(started);
+	}
+	let mpv: &mut Box<dyn Player> = player.as_mut().unwrap();
 	
 	if settings.fullscreen {
 		mpv.set_property("fullscreen", "yes").expect("Failed to configure fullscreen.");
@@ -297,6 +529,8 @@ pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items) {
   }
 	
 	mpv.set_property("user-agent", APPNAME).expect("Failed to configure user-agent.");
+	// The token is sent as a header, so it never shows up in the url.
+	mpv.set_property("http-header-fields", auth_header_field(head_dict).as_str()).expect("Failed to configure http-header-fields.");
 	
 	if item.Type == "Movie" {
 		mpv.set_property("force-media-title", format!("{} ({}) | {}", item.Name, &item.PremiereDate.as_ref().unwrap_or(&"????".to_string())[0..4], head_dict.media_server_name).as_str()).expect("Failed to configure force-media-title.");
@@ -306,73 +540,212 @@ pub fn play(settings: &Settings, head_dict: &HeadDict, Item: &Items) {
 		mpv.set_property("title", format!("{} - Streaming: {} ({}) - {} - {}", APPNAME, item.SeriesName.as_ref().unwrap(), &item.PremiereDate.as_ref().unwrap_or(&"????".to_string())[0..4], item.SeasonName.as_ref().unwrap(), item.Name).as_str()).expect("Failed to configure title.");
 	}
 
-	mpv.command(&["loadfile", &stream_url as &str]).expect("Failed to stream the file :/");
+	// The player might still have the start position of the previous item.
+	mpv.set_property("start", resume_progress.to_string().as_str()).expect("Failed to configure the start position.");
 
-	let mut discord: DiscordClient = discord::mpv_link(settings.discord_presence);
-	let mut old_pos: f64 = -15.0;
-	let mut last_time_update: f64 = 0.0;
-	'main: loop {
-		while let Some(event) = mpv.wait_event(0.0) {
-			match event {
-				mpv::Event::FileLoaded => {
-					if resume_progress != 0 && ! settings.transcoding {
-						mpv.command(&["seek", format!("{}", &resume_progress).as_str()]).expect("Failed to seek");
+	mpv_options::loadfile(mpv.as_mut(), &stream_url, &mpv_options::options_for(settings, head_dict, item)).expect("Failed to stream the file :/");
+
+	let end: PlaybackEnd = watch(settings, head_dict, &session, &media_source, mpv.as_mut(), state);
+	if matches!(end, PlaybackEnd::Closed) || !mpv.reusable() {
+		*player = None;
+	}
+	end
+}
+
+
+fn observe(mpv: &mut dyn Player) {
+	for (id, (property, format)) in OBSERVED_PROPERTIES.iter().enumerate() {
+		mpv.observe_property(id as u64, property, *format).expect("Failed to observe mpv properties.");
+	}
+}
+
+
+// For players, which already have the stream loaded (f.e. pre-buffered episodes), only the play session is missing.
+pub fn play_loaded(settings: &Settings, head_dict: &HeadDict, item: &Items, queue: &[Items], media_source: &MediaSourceInfo, mpv: &mut dyn Player, position: f64) -> PlaybackEnd {
+	let playback_info: Option<PlaybackInfo> = match puddler_get(format!("{}{}/Items/{}/PlaybackInfo?UserId={}&MediaSourceId={}", head_dict.config_file.ipaddress, head_dict.media_server, item.Id, head_dict.config_file.user_id, media_source.Id), head_dict) {
+		Ok(mut t) => t.json::<PlaybackInfo>().ok().filter(|info| !info.MediaSources.is_empty()),
+		Err(_) => None
+	};
+	let Some(playback_info) = playback_info else {
+		println!("{}", "Couldn't start a play session, the progress of this episode won't be saved.".to_string().red());
+		while !matches!(mpv.wait_event(0.5), Some(PlayerEvent::Shutdown) | Some(PlayerEvent::EndFile(_))) {}
+		return PlaybackEnd::Finished
+	};
+	let media_source: MediaSourceInfo = playback_info.MediaSources.iter().find(|source| source.Id == media_source.Id).unwrap_or(&playback_info.MediaSources[0]).clone();
+	let session: PlaySession = PlaySession {
+		item,
+		queue,
+		playsession_id: playback_info.PlaySessionId.clone(),
+		mediasource_id: media_source.Id.clone(),
+		transcoding: false,
+		bitrate: media_source.Bitrate
+	};
+	let state: PlaybackState = PlaybackState {
+		position,
+		..Default::default()
+	};
+	observe(mpv);
+	watch(settings, head_dict, &session, &media_source, mpv, state)
+}
+
+
+// Reports the playback to the server (and everything else that's interested) until mpv is closed.
+fn watch(settings: &Settings, head_dict: &HeadDict, session: &PlaySession, media_source: &MediaSourceInfo, mpv: &mut dyn Player, mut state: PlaybackState) -> PlaybackEnd {
+	let item: &Items = session.item;
+	let queue: &[Items] = session.queue;
+	let transcoding: bool = session.transcoding;
+	// The start is only reported once the file plays, so it contains its tracks and mpv's volume instead of the defaults.
+	// Players, which never report a restart (f.e. already loaded ones or VLC), are started after a short wait.
+	let waiting_since = time::Instant::now();
+	let mut started: bool = false;
+
+	let mut keybindings: Keybindings = Keybindings::register(mpv, item);
+	// Set by the "mark played and next" keybinding.
+	let mut mark_played: bool = false;
+
+	let mut presence: Vec<Box<dyn PresenceBackend>> = presence::backends(settings);
+	let report_interval = time::Duration::from_secs(settings.progress_interval.max(1));
+	let mut last_report = time::Instant::now();
+	let mut stalls: u32 = 0;
+	let queue_position: Option<usize> = queue.iter().position(|queued| queued.Id == item.Id);
+	let mpris: Option<Mpris> = mpris::start(head_dict, item,
+		queue_position.is_some_and(|position| position + 1 < queue.len()),
+		queue_position.is_some_and(|position| position > 0));
+	// After a seek, the new position is only known with the next update of "time-pos".
+	let mut seeked: bool = false;
+	let mut end: PlaybackEnd = PlaybackEnd::Finished;
+	loop {
+		let mut report: Option<&str> = None;
+		if let Some(mpris) = &mpris {
+			mpris.update(&state);
+			for command in mpris.commands() {
+				let result = match command {
+					MprisCommand::PlayPause => mpv.set_property("pause", if state.paused { "no" } else { "yes" }),
+					MprisCommand::Play => mpv.set_property("pause", "no"),
+					MprisCommand::Pause => mpv.set_property("pause", "yes"),
+					MprisCommand::Stop => mpv.command(&["stop"]),
+					// Seeked is emitted once mpv knows the new position.
+					MprisCommand::Seek(offset) => mpv.command(&["seek", &(offset as f64 / 1000000.0).to_string(), "relative"]),
+					MprisCommand::SetPosition(position) => mpv.command(&["seek", &(position as f64 / 1000000.0).to_string(), "absolute"]),
+					MprisCommand::Next | MprisCommand::Previous => {
+						end = if matches!(command, MprisCommand::Next) { PlaybackEnd::Next } else { PlaybackEnd::Previous };
+						mpv.command(&["stop"])
 					}
+				};
+				if let Err(e) = result {
+					println!("Couldn't handle the MPRIS request {command:?}: {e}");
 				}
-				mpv::Event::Shutdown => {
-					finished_playback(settings, head_dict, item, old_pos * 10000000.0, &playback_info.PlaySessionId, &playback_info.MediaSources[0].Id, false);
-					break 'main;
+			}
+		}
+		let event: Option<PlayerEvent> = mpv.wait_event(0.5);
+		if !started && (matches!(event, Some(PlayerEvent::PlaybackRestart) | Some(PlayerEvent::EndFile(_)) | Some(PlayerEvent::Shutdown))
+			|| waiting_since.elapsed() >= time::Duration::from_secs(2)) {
+			started = true;
+			started_playing(head_dict, session, &state);
+			run_hook(settings, HookEvent::Start, head_dict, item, state.position);
+			last_report = time::Instant::now();
+		}
+		if let Some(event) = event {
+			match event {
+				PlayerEvent::Shutdown | PlayerEvent::EndFile(_) if !mpv.reports_position() => {
+					print!("The player couldn't report how far you've watched.\nDid you finish {}?\n (Y)es / (N)o", item.Name);
+					if "Yy".contains(getch("YyNn")) {
+						state.position = item.RunTimeTicks.map(|ticks| ticks as f64 / 10000000.0).unwrap_or(state.position);
+					}
+					finished_playback(settings, head_dict, session, state.position * 10000000.0, true);
+					break;
 				}
-				mpv::Event::EndFile(_t) => {
-					finished_playback(settings, head_dict, item, old_pos * 10000000.0, &playback_info.PlaySessionId, &playback_info.MediaSources[0].Id, true);
-					break 'main;
+				PlayerEvent::Shutdown => {
+					finished_playback(settings, head_dict, session, state.position * 10000000.0, false);
+					end = PlaybackEnd::Closed;
+					break;
 				}
-				mpv::Event::Seek | mpv::Event::PlaybackRestart => {
-					old_pos -= 16.0
+				PlayerEvent::EndFile(eof) => {
+					finished_playback(settings, head_dict, session, state.position * 10000000.0, eof || mark_played);
+					break;
 				}
-				_ => {
-					// println!("{:#?}", event); // for debugging
+				PlayerEvent::PlaybackRestart => {
+					// Fired once a seek has finished and playback continues from the new position.
+					report = Some("TimeUpdate");
+					seeked = true;
 				}
-			};
-		}
-		let result: Result<f64, mpv::Error> = mpv.get_property("time-pos");
-		if let Ok(nice) = result {
-			if nice > old_pos + 15.0 { // this was the most retarded solution, I could think of
-				update_progress(settings, head_dict, item, nice * 10000000.0, false, &playback_info.PlaySessionId, &playback_info.MediaSources[0].Id);
-				if settings.discord_presence {
-					if item.Type == "Movie" {
-						DiscordClient::update_presence(&mut discord, head_dict,
-							"".to_string(),
-							format!("{} ({})", &item.Name, &item.PremiereDate.as_ref().unwrap_or(&"????".to_string())[0..4]),
-							SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs_f64() + total_runtime - nice,
-						);
-					} else {
-						DiscordClient::update_presence(&mut discord, head_dict,
-							format!("{} ({})", &item.SeriesName.as_ref().unwrap(), &item.PremiereDate.as_ref().unwrap_or(&"????".to_string())[0..4]),
-							format!("{} ({})", item.Name, item.SeasonName.as_ref().unwrap()),
-							SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs_f64() + total_runtime - nice,
-						);
+				PlayerEvent::ClientMessage(args) => {
+					binge::touch();
+					if let Some(action) = keybindings.handle(&args, head_dict, item, state.position, mpv) {
+						end = if action == Action::Previous { PlaybackEnd::Previous } else { PlaybackEnd::Next };
+						mark_played = action == Action::PlayedAndNext;
+						mpv.command(&["stop"]).ok();
 					}
 				}
-				old_pos = nice;
-			} else if nice == last_time_update {
-				update_progress(settings, head_dict, item, nice * 10000000.0, true, &playback_info.PlaySessionId, &playback_info.MediaSources[0].Id);
-				if settings.discord_presence {
-					if item.Type == "Movie" {
-						DiscordClient::pause(&mut discord, head_dict,
-							"".to_string(),
-							format!("{} ({})", &item.Name, &item.PremiereDate.as_ref().unwrap_or(&"????".to_string())[0..4]),
-						);
-					} else {
-						DiscordClient::pause(&mut discord, head_dict,
-							format!("{} ({})", &item.SeriesName.as_ref().unwrap(), &item.PremiereDate.as_ref().unwrap_or(&"????".to_string())[0..4]),
-							format!("{} ({})", item.Name, item.SeasonName.as_ref().unwrap()),
-						);
+				PlayerEvent::PropertyChange(name, change) => {
+					report = state.apply(&name, change);
+					if ! transcoding {
+						state.audio_stream_index = media_source.stream_index("Audio", state.audio_track);
+						state.subtitle_stream_index = media_source.stream_index("Subtitle", state.subtitle_track);
+					}
+					if name == "paused-for-cache" && state.buffering {
+						stalls += 1;
+					}
+					if seeked && name == "time-pos" {
+						seeked = false;
+						if let Some(mpris) = &mpris {
+							mpris.seeked(state.position);
+						}
 					}
 				}
+			};
+		}
+		// Until then, property changes are only the initial state of the player.
+		if !started {
+			continue;
+		}
+		if report.is_none() && !state.paused && last_report.elapsed() >= report_interval {
+			report = Some("TimeUpdate");
+		}
+		if let Some(event_name) = report {
+			match event_name {
+				"Pause" => {
+					binge::touch();
+					run_hook(settings, HookEvent::Pause, head_dict, item, state.position)
+				},
+				"Unpause" => {
+					binge::touch();
+					run_hook(settings, HookEvent::Resume, head_dict, item, state.position)
+				},
+				_ => ()
+			}
+			update_progress(head_dict, session, &state, event_name);
+			if !presence.is_empty() {
+				let current = Presence::new(settings, head_dict, item, &state);
+				for backend in presence.iter_mut() {
+					backend.update(&current);
+				}
 			}
-			last_time_update = nice;
+			last_report = time::Instant::now();
+		}
+	}
+	for backend in presence.iter_mut() {
+		backend.clear();
+	}
+	if transcoding && stalls > 0 {
+		print!("Playback stalled {stalls} time(s) while waiting for the server.\nDo you want to re-measure your connection speed for the next stream?\n (Y)es / (N)o");
+		if "Yy".contains(getch("YyNn")) {
+			remeasure_bitrate(head_dict);
 		}
-		thread::sleep(time::Duration::from_millis(500));
 	}
+	end
 }
+
+
+// Plays the queue from the given position, following next/previous requests (f.e. from MPRIS).
+pub fn play_queue(settings: &Settings, head_dict: &HeadDict, queue: &[Items], mut position: usize) {
+	loop {
+		match play(settings, head_dict, &queue[position], queue) {
+			PlaybackEnd::Next if position + 1 < queue.len() => position += 1,
+			PlaybackEnd::Previous if position > 0 => position -= 1,
+			_ => break
+		}
+	}
+}
+
+
@@ -0,0 +1,312 @@
+// Players other than mpv: VLC (controlled through its local HTTP interface) and any other player started from a command template
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant};
+use colored::Colorize;
+use isahc::auth::{Authentication, Credentials};
+use isahc::config::Configurable;
+use isahc::prelude::*;
+use isahc::Request;
+use serde_json::Value;
+use urlencoding::encode;
+use crate::backend::{has_exited, kill, register, Player, PlayerEvent, PropertyFormat, PropertyValue};
+
+
+// Neither VLC nor most other players can send the token as a header, so it has to be part of the url.
+fn with_token(url: &str, properties: &HashMap<String, String>) -> String {
+    match properties.get("http-header-fields").and_then(|header| header.strip_prefix("X-Emby-Token: ")) {
+        Some(token) if url.contains("://") => format!("{}{}api_key={}", url, if url.contains('?') { '&' } else { '?' }, token),
+        _ => url.to_string()
+    }
+}
+
+
+pub struct Vlc {
+    vlc_path: String,
+    // Everything that has been set before the stream is loaded, VLC gets it as arguments.
+    properties: HashMap<String, String>,
+    port: u16,
+    password: String,
+    // The password is read from here, on the command line any local user could use it to query the (tokenized) playlist.
+    config: Option<PathBuf>,
+    pid: Option<u32>,
+    seek_to: Option<f64>,
+    pending: VecDeque<PlayerEvent>
+}
+
+
+impl Vlc {
+    pub fn new(vlc_path: String) -> Vlc {
+        Vlc {
+            vlc_path,
+            properties: HashMap::new(),
+            port: 0,
+            password: uuid::Uuid::new_v4().simple().to_string(),
+            config: None,
+            pid: None,
+            seek_to: None,
+            pending: VecDeque::new()
+        }
+    }
+
+    fn request(&self, query: &str) -> Result<Value, String> {
+        let mut response = Request::get(format!("http://127.0.0.1:{}/requests/status.json{}", self.port, query))
+            .timeout(Duration::from_secs(2))
+            .authentication(Authentication::basic())
+            .credentials(Credentials::new("", self.password.clone()))
+            .body(()).map_err(|e| e.to_string())?
+            .send().map_err(|e| e.to_string())?;
+        response.json::<Value>().map_err(|e| e.to_string())
+    }
+
+    // Only readable by the current user. VLC uses it instead of its own vlcrc for this run.
+    fn write_config(&mut self) -> Result<PathBuf, String> {
+        let path = std::env::temp_dir().join(format!("puddler-vlc-{}.vlcrc", uuid::Uuid::new_v4().simple()));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&path).map_err(|e| format!("Couldn't write the VLC config: {e}"))?;
+        self.config = Some(path.clone());
+        writeln!(file, "[core]\nhttp-password={}", self.password).map_err(|e| format!("Couldn't write the VLC config: {e}"))?;
+        Ok(path)
+    }
+
+    // The stream is handed over through the HTTP interface, so the token never shows up in the process list.
+    fn launch(&mut self, url: &str) -> Result<(), String> {
+        self.port = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).map_err(|e| e.to_string())?.port();
+        let config = self.write_config()?;
+        let mut command = Command::new(&self.vlc_path);
+        command.args([
+            format!("--config={}", config.display()),
+            "--no-one-instance".to_string(),
+            "--play-and-exit".to_string(),
+            "--extraintf=http".to_string(),
+            "--http-host=127.0.0.1".to_string(),
+            format!("--http-port={}", self.port)
+        ]);
+        if self.properties.get("fullscreen").is_some_and(|fullscreen| fullscreen == "yes") {
+            command.arg("--fullscreen");
+        }
+        if let Some(title) = self.properties.get("force-media-title") {
+            command.arg(format!("--meta-title={title}"));
+        }
+        if let Some(subtitle) = self.properties.get("sub-file") {
+            command.arg(format!("--sub-file={subtitle}"));
+        }
+        let child = command.spawn().map_err(|e| format!("Couldn't start VLC (\"{}\"): {e}", self.vlc_path))?;
+        let pid = register(child);
+        self.pid = Some(pid);
+        self.seek_to = self.properties.get("start").and_then(|start| start.parse::<f64>().ok());
+        let query = format!("?command=in_play&input={}", encode(&with_token(url, &self.properties)));
+        let started = Instant::now();
+        loop {
+            match self.request(&query) {
+                Ok(_) => return Ok(()),
+                Err(e) if started.elapsed() > Duration::from_secs(10) => {
+                    kill(pid);
+                    return Err(format!("Couldn't reach the HTTP interface of VLC: {e}"))
+                },
+                Err(_) => thread::sleep(Duration::from_millis(200))
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        let Ok(status) = self.request("") else { return };
+        let playing = status["state"].as_str() == Some("playing");
+        // VLC doesn't know the start position, so it's seeked to once the stream is running.
+        if let Some(start) = self.seek_to {
+            if playing && status["length"].as_f64().is_some_and(|length| length > 0.0) {
+                self.request(&format!("?command=seek&val={}", start.round())).ok();
+                self.seek_to = None;
+                return
+            }
+        }
+        if let Some(time) = status["time"].as_f64() {
+            self.pending.push_back(PlayerEvent::PropertyChange("time-pos".to_string(), PropertyValue::Double(time)));
+        }
+        self.pending.push_back(PlayerEvent::PropertyChange("pause".to_string(), PropertyValue::Flag(status["state"].as_str() == Some("paused"))));
+        if let Some(volume) = status["volume"].as_f64() {
+            // 256 is 100% in VLC.
+            self.pending.push_back(PlayerEvent::PropertyChange("volume".to_string(), PropertyValue::Double((volume / 256.0 * 100.0).round())));
+        }
+        if let Some(rate) = status["rate"].as_f64() {
+            self.pending.push_back(PlayerEvent::PropertyChange("speed".to_string(), PropertyValue::Double(rate)));
+        }
+        let looping = if status["repeat"].as_bool() == Some(true) { "inf" } else { "no" };
+        self.pending.push_back(PlayerEvent::PropertyChange("loop-file".to_string(), PropertyValue::Str(looping.to_string())));
+    }
+}
+
+
+impl Player for Vlc {
+    fn set_property(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if self.pid.is_none() {
+            self.properties.insert(name.to_string(), value.to_string());
+            return Ok(())
+        }
+        match (name, value) {
+            ("pause", "yes") => self.request("?command=pl_forcepause").map(|_| ()),
+            ("pause", _) => self.request("?command=pl_forceresume").map(|_| ()),
+            _ => Err(format!("VLC can't change \"{name}\" during playback."))
+        }
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<(), String> {
+        match args {
+            ["loadfile", url, ..] => self.launch(url),
+            ["stop", ..] => {
+                self.request("?command=pl_stop")?;
+                self.pending.push_back(PlayerEvent::EndFile(false));
+                Ok(())
+            },
+            ["seek", seconds, mode, ..] => {
+                let seconds = seconds.parse::<f64>().map_err(|e| e.to_string())?.round();
+                let value = if *mode == "relative" && seconds >= 0.0 { format!("+{seconds}") } else { seconds.to_string() };
+                self.request(&format!("?command=seek&val={}", encode(&value))).map(|_| ())
+            },
+            _ => Err(format!("VLC doesn't support {args:?}."))
+        }
+    }
+
+    // Everything is polled anyway.
+    fn observe_property(&mut self, _id: u64, _name: &str, _format: PropertyFormat) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn wait_event(&mut self, timeout: f64) -> Option<PlayerEvent> {
+        if let Some(event) = self.pending.pop_front() {
+            return Some(event)
+        }
+        thread::sleep(Duration::from_secs_f64(timeout));
+        let pid = self.pid?;
+        // VLC closes itself at the end (--play-and-exit), the position decides whether it has been played.
+        if has_exited(pid) {
+            return Some(PlayerEvent::EndFile(false))
+        }
+        self.poll();
+        self.pending.pop_front()
+    }
+
+    // VLC closes itself once the stream has ended.
+    fn reusable(&self) -> bool {
+        false
+    }
+}
+
+
+impl Drop for Vlc {
+    fn drop(&mut self) {
+        if let Some(pid) = self.pid {
+            kill(pid);
+        }
+        if let Some(config) = &self.config {
+            fs::remove_file(config).ok();
+        }
+    }
+}
+
+
+// Any player, started from a template like "celluloid {url} --mpv-start={start}".
+// The template is split at spaces, the placeholders can also be part of an argument.
+// With {header} (f.e. "--mpv-http-header-fields={header}") the token stays out of the url, otherwise it's added as "api_key".
+// Both end up on the player's command line.
+pub struct CommandPlayer {
+    template: String,
+    properties: HashMap<String, String>,
+    pid: Option<u32>
+}
+
+
+// The template can also come from the settings file or a flag, so the warning is shown once per run.
+static TOKEN_WARNING: Once = Once::new();
+
+
+impl CommandPlayer {
+    pub fn new(template: String) -> CommandPlayer {
+        let warning = if template.contains("{header}") {
+            "Your access token is passed to the player as {header}, other users of this computer can see it in the process list."
+        } else {
+            "The player command has no {header}, so your access token is passed to the player as part of the url."
+        };
+        TOKEN_WARNING.call_once(|| println!("{}", warning.to_string().yellow()));
+        CommandPlayer {
+            template,
+            properties: HashMap::new(),
+            pid: None
+        }
+    }
+}
+
+
+impl Player for CommandPlayer {
+    fn set_property(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if self.pid.is_some() {
+            return Err("The player can't be controlled.".to_string())
+        }
+        self.properties.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<(), String> {
+        let ["loadfile", url, ..] = args else {
+            return Err("The player can't be controlled.".to_string())
+        };
+        let property = |name: &str, default: &str| self.properties.get(name).cloned().unwrap_or(default.to_string());
+        let url: String = if self.template.contains("{header}") { url.to_string() } else { with_token(url, &self.properties) };
+        let arguments: Vec<String> = self.template.split_whitespace()
+            .map(|argument| argument
+                .replace("{url}", &url)
+                .replace("{header}", &property("http-header-fields", ""))
+                .replace("{title}", &property("force-media-title", ""))
+                .replace("{start}", &property("start", "0"))
+                .replace("{subfile}", &property("sub-file", "")))
+            .filter(|argument| !argument.is_empty())
+            .collect();
+        let Some((program, arguments)) = arguments.split_first() else {
+            return Err("The player command is empty.".to_string())
+        };
+        let child = Command::new(program).args(arguments).spawn().map_err(|e| format!("Couldn't start \"{program}\": {e}"))?;
+        self.pid = Some(register(child));
+        Ok(())
+    }
+
+    fn observe_property(&mut self, _id: u64, _name: &str, _format: PropertyFormat) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn wait_event(&mut self, timeout: f64) -> Option<PlayerEvent> {
+        thread::sleep(Duration::from_secs_f64(timeout));
+        if has_exited(self.pid?) {
+            Some(PlayerEvent::EndFile(false))
+        } else {
+            None
+        }
+    }
+
+    fn reports_position(&self) -> bool {
+        false
+    }
+
+    fn reusable(&self) -> bool {
+        false
+    }
+}
+
+
+impl Drop for CommandPlayer {
+    fn drop(&mut self) {
+        if let Some(pid) = self.pid {
+            kill(pid);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Keeps the upcoming episodes loaded (and paused) in their own mpv instances, so the next one starts instantly
+use std::collections::VecDeque;
+use colored::Colorize;
+use crate::backend::{mpv_args, mpv_path, ExternalMpv, Player};
+use crate::mediaserver_information::HeadDict;
+use crate::mpv_options;
+use crate::player::{play_loaded, PlaybackEnd};
+use crate::progress_report::MediaSourceInfo;
+use crate::settings::Settings;
+use crate::Items;
+
+
+pub struct Episode {
+    pub url: String,
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub header: String,
+    pub start: i32,
+    pub media_source: MediaSourceInfo,
+    pub options: Vec<(String, String)>
+}
+
+
+struct Prefetched {
+    index: usize,
+    start: i32,
+    media_source: MediaSourceInfo,
+    player: ExternalMpv
+}
+
+
+pub struct PrefetchPool {
+    mpv_path: String,
+    mpv_args: Vec<String>,
+    depth: usize,
+    fullscreen: bool,
+    loaded: VecDeque<Prefetched>
+}
+
+
+impl PrefetchPool {
+    pub fn new(settings: &Settings) -> PrefetchPool {
+        PrefetchPool {
+            mpv_path: mpv_path(settings),
+            mpv_args: mpv_args(settings),
+            depth: settings.prefetch_episodes as usize,
+            fullscreen: settings.fullscreen,
+            loaded: VecDeque::new()
+        }
+    }
+
+    // The stream url and token are handed over through the IPC socket, so they never show up in the process list.
+    fn spawn(&self, index: usize, episode: &Episode) -> Option<Prefetched> {
+        let mut args: Vec<String> = [
+            format!("--start={}", episode.start),
+            "--pause".to_string(),
+            "--window-minimized=yes".to_string(),
+            format!("--force-media-title={}", episode.title)
+        ].to_vec();
+        args.extend(self.mpv_args.clone());
+        if let Some(subtitle) = &episode.subtitle {
+            args.push(format!("--sub-file={subtitle}"));
+        }
+        let loaded = ExternalMpv::spawn(&self.mpv_path, &args).and_then(|mut player| {
+            player.set_property("http-header-fields", &episode.header)?;
+            mpv_options::loadfile(&mut player, &episode.url, &episode.options)?;
+            Ok(player)
+        });
+        match loaded {
+            Ok(player) => Some(Prefetched {
+                index,
+                start: episode.start,
+                media_source: episode.media_source.clone(),
+                player
+            }),
+            Err(e) => {
+                println!("{}", format!("Couldn't hand the stream over to mpv: {e}").red());
+                None
+            }
+        }
+    }
+
+    // Dropping the players closes their mpv instances.
+    pub fn unload(&mut self) {
+        self.loaded.clear();
+    }
+
+    // Plays the episode at the given index and returns once its mpv has been closed.
+    // None means the episode couldn't be started at all.
+    pub fn play(&mut self, settings: &Settings, head_dict: &HeadDict, queue: &[Items], index: usize, episode: impl Fn(usize) -> Option<Episode>) -> Option<PlaybackEnd> {
+        // Anything before the requested episode won't be needed anymore (f.e. after jumping around).
+        while self.loaded.front().is_some_and(|prefetched| prefetched.index < index || prefetched.player.has_exited()) {
+            self.loaded.pop_front();
+        }
+        let current = match self.loaded.front() {
+            Some(prefetched) if prefetched.index == index => self.loaded.pop_front(),
+            _ => {
+                self.unload();
+                episode(index).and_then(|episode| self.spawn(index, &episode))
+            }
+        };
+        let mut current = current?;
+        current.player.set_property("window-minimized", "no").ok();
+        current.player.set_property("fullscreen", if self.fullscreen { "yes" } else { "no" }).ok();
+        current.player.set_property("pause", "no").ok();
+
+        let mut next = self.loaded.back().map(|prefetched| prefetched.index + 1).unwrap_or(index + 1);
+        while self.loaded.len() < self.depth {
+            let Some(upcoming) = episode(next) else { break };
+            match self.spawn(next, &upcoming) {
+                Some(prefetched) => self.loaded.push_back(prefetched),
+                None => break
+            }
+            next += 1;
+        }
+
+        Some(play_loaded(settings, head_dict, &queue[index], queue, &current.media_source, &mut current.player, current.start as f64))
+    }
+}
@@ -0,0 +1,124 @@
+// Tells other programs (Discord, status bars, ...) what is currently playing
+use std::fs;
+use std::time::SystemTime;
+use colored::Colorize;
+use crate::discord::DiscordClient;
+use crate::mediaserver_information::HeadDict;
+use crate::player::PlaybackState;
+use crate::settings::Settings;
+use crate::Items;
+
+
+pub struct Presence {
+    pub title: String,
+    // The series (and year) of an episode, or nothing for movies.
+    pub details: Option<String>,
+    pub episode: Option<String>,
+    pub paused: bool,
+    pub position: f64,
+    pub runtime: Option<f64>,
+    // Unix timestamps of the (virtual) start and end of the playback, they follow seeking and playback speed.
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub artwork: Option<String>,
+    pub server: String
+}
+
+
+pub trait PresenceBackend {
+    fn update(&mut self, presence: &Presence);
+    fn clear(&mut self);
+}
+
+
+impl Presence {
+    pub fn new(settings: &Settings, head_dict: &HeadDict, item: &Items, state: &PlaybackState) -> Presence {
+        let year: String = item.PremiereDate.as_ref().map(|date| date.chars().take(4).collect()).unwrap_or("????".to_string());
+        let details = item.SeriesName.as_ref().map(|series| format!("{series} ({year})"));
+        let title = if details.is_some() {
+            item.Name.clone()
+        } else {
+            format!("{} ({})", item.Name, year)
+        };
+        let episode = match (item.ParentIndexNumber, item.IndexNumber) {
+            (Some(season), Some(episode)) => Some(format!("S{season:02}E{episode:02}")),
+            _ => item.SeasonName.clone()
+        };
+        let runtime: Option<f64> = item.RunTimeTicks.map(|ticks| ticks as f64 / 10000000.0);
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs_f64();
+        let start = now - state.position / state.speed;
+        // Posters have to be reachable by Discord, so they're only shown with a public url.
+        let artwork = settings.presence_artwork_url.as_ref().map(|url| format!("{}{}/Items/{}/Images/Primary?maxHeight=512",
+            url.trim_end_matches('/'), head_dict.media_server, item.SeriesId.as_ref().unwrap_or(&item.Id)));
+        Presence {
+            title,
+            details,
+            episode,
+            paused: state.paused,
+            position: state.position,
+            runtime,
+            start: Some(start.round() as i64),
+            end: runtime.map(|runtime| (start + runtime / state.speed).round() as i64),
+            artwork,
+            server: head_dict.media_server_name.clone()
+        }
+    }
+
+    pub fn state(&self) -> String {
+        match &self.episode {
+            Some(episode) => format!("{} - {}", episode, self.title),
+            None => self.title.clone()
+        }
+    }
+}
+
+
+// Writes a single "now playing" line, which status bars can simply read.
+pub struct FilePresence {
+    pub path: String
+}
+
+
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+
+impl PresenceBackend for FilePresence {
+    fn update(&mut self, presence: &Presence) {
+        let mut line = format!("{} {}", if presence.paused { "⏸" } else { "▶" }, presence.state());
+        if let Some(details) = &presence.details {
+            line = format!("{line} | {details}");
+        }
+        line = match presence.runtime {
+            Some(runtime) => format!("{line} [{}/{}]", format_time(presence.position), format_time(runtime)),
+            None => format!("{line} [{}]", format_time(presence.position))
+        };
+        if fs::write(&self.path, format!("{line}\n")).is_err() {
+            println!("{}", format!("Couldn't write the now-playing file \"{}\".", self.path).red());
+        }
+    }
+
+    fn clear(&mut self) {
+        fs::write(&self.path, "").ok();
+    }
+}
+
+
+pub fn backends(settings: &Settings) -> Vec<Box<dyn PresenceBackend>> {
+    let mut backends: Vec<Box<dyn PresenceBackend>> = Vec::new();
+    if settings.discord_presence {
+        backends.push(Box::new(DiscordClient::default()));
+    }
+    if let Some(path) = &settings.presence_file {
+        backends.push(Box::new(FilePresence {
+            path: path.clone()
+        }));
+    }
+    backends
+}
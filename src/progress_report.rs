@@ -1,15 +1,23 @@
 use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use app_dirs::*;
 use colored::Colorize;
 use serde::Serialize;
 use isahc::Request;
 use isahc::prelude::*;
 use crate::mediaserver_information::AuthHeader;
 use crate::settings::Settings;
+use crate::player::PlaybackState;
+use crate::history;
+use crate::hooks::{run_hook, HookEvent};
 use serde_derive::{Deserialize};
 extern crate mpv;
 use crate::{
     HeadDict,
-    Items
+    Items,
+    APP_INFO
 };
 
 
@@ -20,9 +28,15 @@ struct PlaybackObject {
     playsessionid: String,
     mediasourceid: String,
     ispaused: bool,
+    ismuted: bool,
+    volumelevel: i64,
+    audiostreamindex: Option<usize>,
+    subtitlestreamindex: Option<usize>,
+    playbackrate: f64,
     positionticks: String,
     playmethod: String,
-    repeastmode: String,
+    repeatmode: String,
+    nowplayingqueue: Vec<QueueItem>,
     eventname: String
 }
 
@@ -35,9 +49,29 @@ struct PlayingObject {
     mediasourceid: String,
     ispaused: bool,
     ismuted: bool,
+    volumelevel: i64,
+    audiostreamindex: Option<usize>,
+    subtitlestreamindex: Option<usize>,
+    playbackrate: f64,
     playbackstarttimeticks: String,
     playmethod: String,
-    repeatmode: String
+    repeatmode: String,
+    nowplayingqueue: Vec<QueueItem>
+}
+
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+struct QueueItem {
+    id: String,
+    playlistitemid: String
+}
+
+
+fn now_playing_queue(queue: &[Items]) -> Vec<QueueItem> {
+    queue.iter().enumerate().map(|(index, item)| QueueItem {
+        id: item.Id.clone(),
+        playlistitemid: format!("playlistItem{index}")
+    }).collect()
 }
 
 
@@ -48,9 +82,12 @@ pub struct PlaybackInfo {
 }
 
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct MediaSourceInfo {
     pub Id: String,
+    pub Name: Option<String>,
+    pub Container: Option<String>,
+    pub Size: Option<u64>,
     pub SupportsTranscoding: bool,
     pub MediaStreams: Vec<MediaStream>,
     pub Bitrate: Option<u64>,
@@ -58,7 +95,7 @@ pub struct MediaSourceInfo {
 }
 
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct MediaStream {
     pub Index: usize,
     pub Type: String,
@@ -69,10 +106,64 @@ pub struct MediaStream {
     pub Codec: Option<String>,
     pub Width: Option<u32>,
     pub Height: Option<u32>,
+    pub BitRate: Option<u64>,
+    pub IsExternal: Option<bool>,
+    pub VideoRange: Option<String>,
     pub IsDefault: bool
 }
 
 
+impl MediaSourceInfo {
+    pub fn video_stream(&self) -> Option<&MediaStream> {
+        self.MediaStreams.iter().find(|stream| stream.Type == "Video")
+    }
+
+    // mpv numbers the embedded tracks of each type starting at 1, the server uses one index for all streams.
+    pub fn stream_index(&self, kind: &str, mpv_track: Option<i64>) -> Option<usize> {
+        let position = usize::try_from(mpv_track?).ok()?.checked_sub(1)?;
+        self.MediaStreams.iter()
+            .filter(|stream| stream.Type == kind && ! stream.IsExternal.unwrap_or(false))
+            .nth(position)
+            .map(|stream| stream.Index)
+    }
+
+    // The other way around, f.e. to select a track the user has chosen from the server's streams.
+    pub fn mpv_track(&self, kind: &str, stream_index: usize) -> Option<i64> {
+        self.MediaStreams.iter()
+            .filter(|stream| stream.Type == kind && ! stream.IsExternal.unwrap_or(false))
+            .position(|stream| stream.Index == stream_index)
+            .map(|position| position as i64 + 1)
+    }
+}
+
+
+impl fmt::Display for MediaSourceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let video = self.video_stream();
+        let resolution = match video {
+            Some(MediaStream { Width: Some(width), Height: Some(height), .. }) => format!("{width}x{height}"),
+            _ => "???".to_string()
+        };
+        let bitrate = match self.Bitrate.or(video.and_then(|v| v.BitRate)) {
+            Some(bitrate) => format!("{:.1} Mbps", bitrate as f64 / 1000000.0),
+            None => "???".to_string()
+        };
+        let size = match self.Size {
+            Some(size) => format!("{:.2} GB", size as f64 / 1000000000.0),
+            None => "???".to_string()
+        };
+        write!(f, "Name = \"{}\", Resolution = \"{}\", Bitrate = \"{}\", Codec = \"{}\", Range = \"{}\", Size = \"{}\"",
+            self.Name.as_ref().unwrap_or(&"".to_string()),
+            resolution,
+            bitrate,
+            video.and_then(|v| v.Codec.as_ref()).unwrap_or(&"???".to_string()).to_uppercase(),
+            video.and_then(|v| v.VideoRange.as_ref()).unwrap_or(&"???".to_string()),
+            size
+        )
+    }
+}
+
+
 impl fmt::Display for MediaStream {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.IsDefault {
@@ -84,27 +175,53 @@ impl fmt::Display for MediaStream {
 }
 
 
-pub fn started_playing(settings: &Settings, head_dict: &HeadDict, item: &Items, playback_info: &PlaybackInfo) {
+// Everything the server needs to identify one playback.
+pub struct PlaySession<'a> {
+    pub item: &'a Items,
+    pub queue: &'a [Items],
+    pub playsession_id: String,
+    pub mediasource_id: String,
+    pub transcoding: bool,
+    pub bitrate: Option<u64>
+}
+
+
+impl PlaySession<'_> {
+    pub fn playmethod(&self) -> String {
+        if self.transcoding {
+            "Transcode".to_string()
+        } else {
+            "DirectPlay".to_string()
+        }
+    }
+}
+
+
+pub fn started_playing(head_dict: &HeadDict, session: &PlaySession, state: &PlaybackState) {
+    history::record_start(head_dict, session, state);
+    if head_dict.offline {
+        return
+    }
     let ipaddress: &String = &head_dict.config_file.ipaddress;
-    let item_id: &String = &item.Id;
+    let item_id: &String = &session.item.Id;
     let session_id: &String = &head_dict.session_id;
     let media_server: &String = &head_dict.media_server;
     let media_server_name: &String = &head_dict.media_server_name;
-    let playmethod = if settings.transcoding {
-        "Transcode".to_string()
-    } else {
-        "DirectPlay".to_string()
-    };
     let playing_object = PlayingObject {
         itemid: item_id.to_string(),
-        playsessionid: playback_info.PlaySessionId.to_string(),
+        playsessionid: session.playsession_id.to_string(),
         sessionid: session_id.to_string(),
-        mediasourceid: playback_info.MediaSources[0].Id.to_string(),
-        ispaused: false,
-        ismuted: false,
-        playbackstarttimeticks: item.UserData.PlaybackPositionTicks.to_string(),
-        playmethod,
-        repeatmode: "RepeatNone".to_string()
+        mediasourceid: session.mediasource_id.to_string(),
+        ispaused: state.paused,
+        ismuted: state.muted,
+        volumelevel: state.volume.round() as i64,
+        audiostreamindex: state.audio_stream_index,
+        subtitlestreamindex: state.subtitle_stream_index,
+        playbackrate: state.speed,
+        playbackstarttimeticks: session.item.UserData.PlaybackPositionTicks.to_string(),
+        playmethod: session.playmethod(),
+        repeatmode: state.repeat_mode.to_string(),
+        nowplayingqueue: now_playing_queue(session.queue)
     };
     let post_res = no_res_post(format!("{ipaddress}{media_server}/Sessions/Playing?format=json"), &head_dict.auth_header, serde_json::to_string_pretty(&playing_object).unwrap());
     if let Err(error) = post_res {
@@ -113,32 +230,31 @@ pub fn started_playing(settings: &Settings, head_dict: &HeadDict, item: &Items,
 }
 
 
-pub fn update_progress(settings: &Settings, head_dict: &HeadDict, item: &Items, mut time_pos: f64, paused: bool, playsession_id: &String, mediasource_id: &String) {
+pub fn update_progress(head_dict: &HeadDict, session: &PlaySession, state: &PlaybackState, event_name: &str) {
+    history::record_progress(session, state);
+    if head_dict.offline {
+        return
+    }
     let ipaddress: &String = &head_dict.config_file.ipaddress;
-    let item_id: &String = &item.Id;
+    let item_id: &String = &session.item.Id;
     let media_server: &String = &head_dict.media_server;
     let media_server_name: &String = &head_dict.media_server_name;
-    let event_name: String = if paused {
-        "Pause".to_string()
-    } else {
-        "TimeUpdate".to_string()
-    };
-    let playmethod: String;
-    (playmethod, time_pos) = if settings.transcoding {
-        ("Transcode".to_string(), time_pos + item.UserData.PlaybackPositionTicks as f64)
-    } else {
-        ("DirectPlay".to_string(), time_pos)
-    };
     let update_obj = PlaybackObject {
         canseek: true,
         itemid: item_id.to_string(),
-        playsessionid: playsession_id.to_string(),
-        mediasourceid: mediasource_id.to_string(),
-        ispaused: paused,
-        positionticks: time_pos.round().to_string(),
-        playmethod,
-        repeastmode: "RepeatNone".to_string(),
-        eventname: event_name
+        playsessionid: session.playsession_id.to_string(),
+        mediasourceid: session.mediasource_id.to_string(),
+        ispaused: state.paused,
+        ismuted: state.muted,
+        volumelevel: state.volume.round() as i64,
+        audiostreamindex: state.audio_stream_index,
+        subtitlestreamindex: state.subtitle_stream_index,
+        playbackrate: state.speed,
+        positionticks: (state.position * 10000000.0).round().to_string(),
+        playmethod: session.playmethod(),
+        repeatmode: state.repeat_mode.to_string(),
+        nowplayingqueue: now_playing_queue(session.queue),
+        eventname: event_name.to_string()
     };
     let result = no_res_post(format!("{ipaddress}{media_server}/Sessions/Playing/Progress"), &head_dict.auth_header, serde_json::to_string_pretty(&update_obj).unwrap());
     if let Err(error) = result {
@@ -177,38 +293,106 @@ struct NoProgressObject {
 }
 
 
-pub fn finished_playback(settings: &Settings, head_dict: &HeadDict, item: &Items, mut time_pos: f64, playsession_id: &String, mediasource_id: &String, eof: bool) {
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ServerConfiguration {
+    MinResumePct: Option<f64>,
+    MaxResumePct: Option<f64>,
+    MinResumeDurationSeconds: Option<u64>
+}
+
+
+// The server decides when an item counts as played or resumable.
+pub struct ResumeThresholds {
+    pub min_resume_pct: f64,
+    pub max_resume_pct: f64,
+    pub min_resume_duration_seconds: u64
+}
+
+
+#[derive(PartialEq)]
+enum PlaybackOutcome {
+    Played,
+    Resumable,
+    NoProgress
+}
+
+
+// Only administrators are allowed to read the server configuration, everyone else gets the defaults.
+// Requested once per login and kept in the HeadDict.
+pub fn server_configuration(head_dict: &HeadDict) -> ServerConfiguration {
     let ipaddress: &String = &head_dict.config_file.ipaddress;
+    let media_server: &String = &head_dict.media_server;
+    Request::get(format!("{ipaddress}{media_server}/System/Configuration"))
+    .timeout(Duration::from_secs(5))
+    .header("X-Application", &head_dict.request_header.application)
+    .header("X-Emby-Token", &head_dict.request_header.token)
+    .body(()).ok()
+    .and_then(|request| request.send().ok())
+    .filter(|response| response.status().is_success())
+    .and_then(|mut response| response.json::<ServerConfiguration>().ok())
+    .unwrap_or_default()
+}
+
+
+pub fn resume_thresholds(settings: &Settings, head_dict: &HeadDict) -> ResumeThresholds {
+    let server_configuration: &ServerConfiguration = &head_dict.server_configuration;
+    ResumeThresholds {
+        min_resume_pct: settings.min_resume_pct.or(server_configuration.MinResumePct).unwrap_or(5.0),
+        max_resume_pct: settings.max_resume_pct.or(server_configuration.MaxResumePct).unwrap_or(90.0),
+        min_resume_duration_seconds: settings.min_resume_duration_seconds.or(server_configuration.MinResumeDurationSeconds).unwrap_or(300)
+    }
+}
+
+
+fn playback_outcome(thresholds: &ResumeThresholds, runtime_ticks: Option<u64>, time_pos: f64, eof: bool) -> PlaybackOutcome {
+    let runtime_ticks = match runtime_ticks {
+        Some(ticks) if ticks > 0 => ticks as f64,
+        // Without a runtime there is nothing to compare against, only reaching the end counts as played.
+        _ => {
+            return if eof {
+                PlaybackOutcome::Played
+            } else if time_pos > 0.0 {
+                PlaybackOutcome::Resumable
+            } else {
+                PlaybackOutcome::NoProgress
+            }
+        }
+    };
+    let played_pct = time_pos / runtime_ticks * 100.0;
+    if played_pct < thresholds.min_resume_pct {
+        PlaybackOutcome::NoProgress
+    } else if played_pct > thresholds.max_resume_pct || time_pos >= runtime_ticks {
+        PlaybackOutcome::Played
+    } else if runtime_ticks / 10000000.0 < thresholds.min_resume_duration_seconds as f64 {
+        // Short items can't be resumed, so they're played once they got past the minimum.
+        PlaybackOutcome::Played
+    } else {
+        PlaybackOutcome::Resumable
+    }
+}
+
+
+pub fn finished_playback(settings: &Settings, head_dict: &HeadDict, session: &PlaySession, time_pos: f64, eof: bool) {
+    let item: &Items = session.item;
+    let playsession_id: &String = &session.playsession_id;
+    let mediasource_id: &String = &session.mediasource_id;
     let item_id: &String = &item.Id;
     let session_id: &String = &head_dict.session_id;
-    let media_server: &String = &head_dict.media_server;
     let user_id: &String = &head_dict.config_file.user_id;
-    if settings.transcoding {
-        time_pos += item.UserData.PlaybackPositionTicks as f64
-    };
-    if ! eof {
-        let result = no_res_post(format!("{ipaddress}{media_server}/Users/{user_id}/PlayedItems/{item_id}"), &head_dict.auth_header, "".to_string());
-        match result {
-            Ok(_) => {
+    let thresholds: ResumeThresholds = resume_thresholds(settings, head_dict);
+    let outcome: PlaybackOutcome = playback_outcome(&thresholds, item.RunTimeTicks, time_pos, eof);
+    history::record_stop(session, time_pos / 10000000.0, outcome == PlaybackOutcome::Played);
+    run_hook(settings, HookEvent::Stop, head_dict, item, time_pos / 10000000.0);
+    match outcome {
+        PlaybackOutcome::Played => {
+            run_hook(settings, HookEvent::Played, head_dict, item, time_pos / 10000000.0);
+            if post_or_queue(head_dict, format!("/Users/{user_id}/PlayedItems/{item_id}"), "".to_string()) {
                 println!("Item has been marked as [PLAYED].")
+            } else {
+                println!("Couldn't reach your server. The item will be marked as [PLAYED] once it's reachable again.")
             }
-            Err(_) => {
-                println!("Couldn't report item as [PLAYED].")
-            }
-        };
-    } else {
-        let difference = ((item.RunTimeTicks.unwrap() as f64) - time_pos) / (item.RunTimeTicks.unwrap() as f64);
-        if difference < 0.10 {
-            let result = no_res_post(format!("{ipaddress}{media_server}/Users/{user_id}/PlayedItems/{item_id}"), &head_dict.auth_header, "".to_string());
-            match result {
-                Ok(_) => {
-                    println!("Since you've watched more than 90% of the video, it has been marked as [PLAYED].")
-                }
-                Err(_) => {
-                    println!("Couldn't report item as [PLAYED].")
-                }
-            };
-        } else if difference < 0.90 {
+        },
+        PlaybackOutcome::Resumable => {
             let finished_obj = FinishedObject {
                 itemid: item_id.to_string(),
                 playsessionid: playsession_id.to_string(),
@@ -216,26 +400,23 @@ pub fn finished_playback(settings: &Settings, head_dict: &HeadDict, item: &Items
                 mediasourceid: mediasource_id.to_string(),
                 positionticks: time_pos.to_string()
             };
-            let response = no_res_post(format!("{ipaddress}{media_server}/Sessions/Playing/Stopped"), &head_dict.auth_header, serde_json::to_string_pretty(&finished_obj).unwrap());
-            match response {
-                Ok(_) => {
-                    let time = time_pos / 10000000.0;
-                    let formated: String = if time > 60.0 {
-                        if (time / 60.0) > 60.0 {
-                            format!("{:02}:{:02}:{:02}", ((time / 60.0) / 60.0).trunc(), ((((time / 60.0) / 60.0) - ((time / 60.0) / 60.).trunc()) * 60.0).trunc(), (((time / 60.0) - (time / 60.0).trunc()) * 60.0).trunc())
-                        } else {
-                            format!("00:{:02}:{:02}", (time / 60.0).trunc(), (((time / 60.0) - (time / 60.0).trunc()) * 60.0).trunc())
-                        }
+            if post_or_queue(head_dict, "/Sessions/Playing/Stopped".to_string(), serde_json::to_string_pretty(&finished_obj).unwrap()) {
+                let time = time_pos / 10000000.0;
+                let formated: String = if time > 60.0 {
+                    if (time / 60.0) > 60.0 {
+                        format!("{:02}:{:02}:{:02}", ((time / 60.0) / 60.0).trunc(), ((((time / 60.0) / 60.0) - ((time / 60.0) / 60.).trunc()) * 60.0).trunc(), (((time / 60.0) - (time / 60.0).trunc()) * 60.0).trunc())
                     } else {
-                        time.to_string()
-                    };
-                    println!("Playback progress ({formated}) has been sent to your server.")
-                }
-                Err(_) => {
-                    println!("Playback progress couldn't be logged to your server.")
-                }
+                        format!("00:{:02}:{:02}", (time / 60.0).trunc(), (((time / 60.0) - (time / 60.0).trunc()) * 60.0).trunc())
+                    }
+                } else {
+                    time.to_string()
+                };
+                println!("Playback progress ({formated}) has been sent to your server.")
+            } else {
+                println!("Couldn't reach your server. The playback progress will be sent once it's reachable again.")
             }
-        } else {
+        },
+        PlaybackOutcome::NoProgress => {
             let finished_obj = NoProgressObject {
                 itemid: item_id.to_string(),
                 playsessionid: playsession_id.to_string(),
@@ -243,15 +424,84 @@ pub fn finished_playback(settings: &Settings, head_dict: &HeadDict, item: &Items
                 mediasourceid: mediasource_id.to_string(),
                 positionticks: (item.UserData.PlaybackPositionTicks as f64).to_string()
             };
-            let response = no_res_post(format!("{ipaddress}{media_server}/Sessions/Playing/Stopped"), &head_dict.auth_header, serde_json::to_string_pretty(&finished_obj).unwrap());
-            match response {
-                Ok(_) => {
-                    println!("Item has not been marked as [PLAYED].")
-                }
-                Err(_) => {
-                    println!("Playback progress couldn't be logged to your server.")
-                }
+            if post_or_queue(head_dict, "/Sessions/Playing/Stopped".to_string(), serde_json::to_string_pretty(&finished_obj).unwrap()) {
+                println!("Item has not been marked as [PLAYED].")
+            } else {
+                println!("Couldn't reach your server. The playback report will be sent once it's reachable again.")
             }
         }
     }
 }
+
+
+// Reports that couldn't be delivered, because the server wasn't reachable (f.e. while watching downloads offline).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct PendingReport {
+    config_path: String,
+    user_id: String,
+    path: String,
+    body: String
+}
+
+
+fn pending_reports_path() -> PathBuf {
+    get_app_root(AppDataType::UserData, &APP_INFO).unwrap().join("pending_reports.json")
+}
+
+
+fn read_pending_reports() -> Vec<PendingReport> {
+    match fs::read_to_string(pending_reports_path()) {
+        Ok(reports) => serde_json::from_str(&reports).unwrap_or_default(),
+        Err(_) => Vec::new()
+    }
+}
+
+
+fn write_pending_reports(reports: &Vec<PendingReport>) {
+    let path = pending_reports_path();
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).ok();
+    }
+    if fs::write(path, serde_json::to_string_pretty(reports).unwrap()).is_err() {
+        println!("{}", "Couldn't save the playback report for later.".to_string().red());
+    }
+}
+
+
+// Returns false, if the report has been queued instead of being sent.
+fn post_or_queue(head_dict: &HeadDict, path: String, body: String) -> bool {
+    if ! head_dict.offline && no_res_post(format!("{}{}{}", head_dict.config_file.ipaddress, head_dict.media_server, path), &head_dict.auth_header, body.clone()).is_ok() {
+        return true
+    }
+    let mut reports = read_pending_reports();
+    reports.push(PendingReport {
+        config_path: head_dict.config_path.clone(),
+        user_id: head_dict.config_file.user_id.clone(),
+        path,
+        body
+    });
+    write_pending_reports(&reports);
+    false
+}
+
+
+pub fn flush_pending_reports(head_dict: &HeadDict) {
+    let reports = read_pending_reports();
+    if reports.is_empty() {
+        return
+    }
+    let (ours, mut remaining): (Vec<PendingReport>, Vec<PendingReport>) = reports.into_iter()
+        .partition(|report| report.config_path == head_dict.config_path && report.user_id == head_dict.config_file.user_id);
+    let mut sent: usize = 0;
+    for report in ours {
+        if no_res_post(format!("{}{}{}", head_dict.config_file.ipaddress, head_dict.media_server, report.path), &head_dict.auth_header, report.body.clone()).is_ok() {
+            sent += 1;
+        } else {
+            remaining.push(report);
+        }
+    }
+    write_pending_reports(&remaining);
+    if sent > 0 {
+        println!("{}", format!("Sent {sent} playback report(s) from your offline sessions to {}.", head_dict.media_server_name).green());
+    }
+}
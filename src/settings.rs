@@ -1,5 +1,4 @@
 use colored::Colorize;
-use config::{Config, File};
 use std::fs;
 use toml;
 use std::io;
@@ -11,135 +10,406 @@ use app_dirs::*;
 use serde_derive::{Deserialize,Serialize};
 use crate::APPNAME;
 use crate::APP_INFO;
+use crate::arguments;
+use std::collections::BTreeMap;
+use crate::hooks::{HookEvent, Hooks};
+use crate::mpv_options::MpvProfile;
+use crate::config::{choose_config, read_overrides, write_overrides};
 
 
+// Bumped whenever a setting is renamed or changes its meaning, see migrate().
+const SETTINGS_VERSION: u32 = 1;
+
+
+// Missing settings get their default value, so new settings don't break older files.
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Settings {
+  pub settings_version: u32,
   pub server_config: Option<String>,
   pub discord_presence: bool,
   pub transcoding: bool,
   pub fullscreen: bool,
   pub autologin: bool,
   pub autoplay: bool,
-  pub gpu: bool
+  pub gpu: bool,
+  pub version_preference: String,
+  pub progress_interval: u64,
+  // Local overrides for the server's played/resume thresholds.
+  pub min_resume_pct: Option<f64>,
+  pub max_resume_pct: Option<f64>,
+  pub min_resume_duration_seconds: Option<u64>,
+  // Where downloaded items are stored (defaults to the app's data directory).
+  pub download_folder: Option<String>,
+  pub download_concurrency: u64,
+  // Total download speed limit in mbps.
+  pub download_bandwidth: Option<u64>,
+  // A file, which always contains what's currently playing (f.e. for status bars).
+  pub presence_file: Option<String>,
+  // The public url of the server, which Discord uses to show posters.
+  pub presence_artwork_url: Option<String>,
+  // The built-in "libmpv", an "external" mpv (controlled through its JSON IPC), "vlc" or a custom "command".
+  pub player_backend: String,
+  // The external mpv executable (defaults to "mpv" from the PATH), always used for series.
+  pub mpv_path: Option<String>,
+  // Series look for a matching "*S*<season>E<episode>*.ass" in here.
+  pub subtitle_folder: Option<String>,
+  pub vlc_path: Option<String>,
+  // A command like "celluloid {url}", which is used by the "command" player.
+  pub player_command: Option<String>,
+  // How many upcoming episodes are kept loaded in the background.
+  pub prefetch_episodes: u64,
+  // Gives the player its own mpv.conf, input.conf and scripts.
+  pub mpv_config_dir: Option<String>,
+  // Seconds until binge mode continues with the next episode.
+  pub binge_countdown: u64,
+  // Binge mode asks whether you're still watching after this many episodes or hours without any interaction ("0" disables it).
+  pub binge_episodes: u64,
+  pub binge_hours: u64,
+  // Commands or urls, which are called on playback events.
+  pub hooks: Hooks,
+  // Options for every played item, f.e. "sub-auto" = "fuzzy".
+  pub mpv_options: BTreeMap<String, String>,
+  // Named sets of options, only used for certain libraries, series or item types.
+  pub mpv_profiles: BTreeMap<String, MpvProfile>
 }
 
 
-fn read_settings() -> Settings {
+impl Default for Settings {
+  fn default() -> Self {
+    Self {
+      settings_version: SETTINGS_VERSION,
+      server_config: None,
+      discord_presence: false,
+      transcoding: false,
+      fullscreen: true,
+      autologin: false,
+      autoplay: false,
+      gpu: false,
+      version_preference: "ask".to_string(),
+      progress_interval: 10,
+      min_resume_pct: None,
+      max_resume_pct: None,
+      min_resume_duration_seconds: None,
+      download_folder: None,
+      download_concurrency: 2,
+      download_bandwidth: None,
+      presence_file: None,
+      presence_artwork_url: None,
+      player_backend: "libmpv".to_string(),
+      mpv_path: None,
+      subtitle_folder: None,
+      vlc_path: None,
+      player_command: None,
+      prefetch_episodes: 0,
+      mpv_config_dir: None,
+      binge_countdown: 10,
+      binge_episodes: 3,
+      binge_hours: 2,
+      hooks: Hooks::default(),
+      mpv_options: BTreeMap::new(),
+      mpv_profiles: BTreeMap::new()
+    }
+  }
+}
+
+
+// Settings, which can be different for every server (f.e. transcoding only for the remote one).
+// They're stored in the config file of the server and replace the global ones after logging in.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SettingsOverrides {
+  pub transcoding: Option<bool>,
+  pub gpu: Option<bool>,
+  pub fullscreen: Option<bool>,
+  pub autoplay: Option<bool>
+}
+
+
+impl SettingsOverrides {
+  fn fields(&self) -> [(&'static str, Option<bool>); 4] {
+    [("transcoding", self.transcoding), ("gpu", self.gpu), ("fullscreen", self.fullscreen), ("autoplay", self.autoplay)]
+  }
+
+  pub fn is_set(&self) -> bool {
+    self.fields().iter().any(|(_, value)| value.is_some())
+  }
+}
+
+
+impl std::fmt::Display for SettingsOverrides {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let set: Vec<String> = self.fields().iter().filter_map(|(name, value)| value.map(|value| format!("{name} = {value}"))).collect();
+    if set.is_empty() {
+      write!(f, "None")
+    } else {
+      write!(f, "{}", set.join(", "))
+    }
+  }
+}
+
+
+impl Settings {
+  // Flags and environment variables of this run still take precedence.
+  pub fn apply_overrides(&mut self, overrides: &SettingsOverrides) {
+    let arguments = arguments::get();
+    let server = |key: &str, value: Option<bool>| value.filter(|_| ! arguments.is_overridden(key));
+    self.transcoding = server("transcoding", overrides.transcoding).unwrap_or(self.transcoding);
+    self.gpu = server("gpu", overrides.gpu).unwrap_or(self.gpu);
+    self.fullscreen = server("fullscreen", overrides.fullscreen).unwrap_or(self.fullscreen);
+    self.autoplay = server("autoplay", overrides.autoplay).unwrap_or(self.autoplay);
+  }
+}
+
+
+fn settings_path() -> String {
+  if let Some(path) = &arguments::get().config {
+    return path.clone()
+  }
   let config_path = get_app_root(AppDataType::UserConfig, &APP_INFO).unwrap();
-  let config_path_string = format!("{}/{}.toml", &config_path.display().to_string(), &APPNAME);
-  if ! Path::new(&config_path_string).is_file() {
+  format!("{}/{}.toml", &config_path.display().to_string(), &APPNAME)
+}
+
+
+fn save_settings(settings: &Settings) {
+  let settings_file = toml::to_string_pretty(settings).unwrap();
+  std::fs::write(settings_path(), settings_file).expect("Saving settings failed.");
+}
+
+
+// Keeps a copy of the settings file before it's changed automatically.
+fn backup_settings(suffix: &str) {
+  let backup_path = format!("{}.{}.bak", settings_path(), suffix);
+  match fs::copy(settings_path(), &backup_path) {
+    Ok(_) => println!("The old settings have been saved to \"{backup_path}\"."),
+    Err(e) => println!("{}", format!("Couldn't back up the settings: {e}").red())
+  }
+}
+
+
+fn initial_settings() -> Settings {
+  // Default <> server.
+  let server_config: Option<String> = search_server_configs();
+  // Discord Presence default setting.
+  let discord_presence: bool = initiate_discord();
+  // Activate encoded streaming (requires fully configured media-server).
+  let transcoding: bool = transcoding();
+  // Whether mpv should start in fullscreen mode.
+  let fullscreen: bool = start_fullscreen();
+  // Whether the user should be prompted if the default login is correct.
+  let autologin: bool = automatically_login();
+  // Whether the user should be prompted to continue after an episode has been finished.
+  let autoplay: bool = autoplay();
+  // Whether mpv should try to use hardware decoding.
+  let gpu: bool = gpu();
+  // Which version should be played, if an item has multiple media-sources.
+  let version_preference: String = version_preference();
+  // How often the playback progress should be sent to the media-server.
+  let progress_interval: u64 = progress_interval();
+  // How many items should be downloaded at the same time.
+  let download_concurrency: u64 = download_concurrency();
+  // Whether the built-in libmpv or an external mpv should be used.
+  let player_backend: String = player_backend();
+  // How many of the next episodes should already be loaded while watching a series.
+  let prefetch_episodes: u64 = prefetch_episodes();
+
+  Settings {
+    server_config,
+    discord_presence,
+    transcoding,
+    fullscreen,
+    autologin,
+    autoplay,
+    gpu,
+    version_preference,
+    progress_interval,
+    download_concurrency,
+    player_backend,
+    prefetch_episodes,
+    ..Default::default()
+  }
+}
+
+
+// The old fix for missing settings appended them without a newline, so several of them can share one line.
+fn split_appended(line: &str) -> Vec<String> {
+  let keys: Vec<String> = toml::Table::try_from(Settings::default()).map(|table| table.keys().cloned().collect()).unwrap_or_default();
+  let mut splits: Vec<usize> = keys.iter()
+    .flat_map(|key| line.match_indices(&format!("{key} = ")).map(|(index, _)| index).collect::<Vec<usize>>())
+    .filter(|index| *index > 0 && ! line[..*index].ends_with(char::is_whitespace))
+    .collect();
+  splits.sort();
+  let mut lines: Vec<String> = Vec::new();
+  let mut start: usize = 0;
+  for split in splits {
+    lines.push(line[start..split].to_string());
+    start = split;
+  }
+  lines.push(line[start..].to_string());
+  lines
+}
+
+
+// Files from before the versioned schema could contain Rust syntax (`= None`, `= Some(...)`),
+// which was appended by the old fix for missing settings.
+fn repair_unversioned(raw: &str) -> String {
+  raw.lines()
+    .flat_map(split_appended)
+    .filter(|line| ! line.trim_end().ends_with("= None"))
+    .map(|line| match line.split_once("= Some(") {
+      Some((key, value)) if value.trim_end().ends_with(')') => format!("{}= {}", key, value.trim_end().trim_end_matches(')')),
+      _ => line.to_string()
+    })
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+
+// The old fix for missing settings appended them at the end of the file, which was inside of [hooks] once it existed.
+// They are moved back to the top, unless the setting is there already.
+fn migrate_v0(table: &mut toml::Table) {
+  let defaults: toml::Table = toml::Table::try_from(Settings::default()).unwrap_or_default();
+  let Some(toml::Value::Table(hooks)) = table.get_mut("hooks") else { return };
+  let stray: Vec<String> = hooks.keys().filter(|key| defaults.contains_key(*key)).cloned().collect();
+  let stray: Vec<(String, toml::Value)> = stray.into_iter().filter_map(|key| hooks.remove(&key).map(|value| (key, value))).collect();
+  for (key, value) in stray {
+    table.entry(key).or_insert(value);
+  }
+}
+
+
+// Every step brings the settings one version further, settings that are missing simply get their default value.
+const MIGRATIONS: [fn(&mut toml::Table); SETTINGS_VERSION as usize] = [migrate_v0];
+
+
+fn migrate(table: &mut toml::Table, from: u32) {
+  for version in from..SETTINGS_VERSION {
+    MIGRATIONS[version as usize](table);
+    println!("Migrated the settings from version {} to {}.", version, version + 1);
+  }
+  table.insert("settings_version".to_string(), toml::Value::Integer(SETTINGS_VERSION as i64));
+}
+
+
+// Settings with invalid values fall back to their defaults, all other settings are kept.
+fn validate(mut table: toml::Table) -> (Settings, Vec<(String, String)>) {
+  let mut invalid: Vec<(String, String)> = Vec::new();
+  for (key, value) in table.clone() {
+    let single: toml::Table = [(key.clone(), value)].into_iter().collect();
+    if let Err(e) = toml::Value::Table(single).try_into::<Settings>() {
+      invalid.push((key.clone(), e.message().to_string()));
+      table.remove(&key);
+    }
+  }
+  let mut settings: Settings = toml::Value::Table(table).try_into::<Settings>().unwrap_or_default();
+  let defaults = Settings::default();
+  if settings.progress_interval == 0 {
+    invalid.push(("progress_interval".to_string(), "has to be at least 1 second".to_string()));
+    settings.progress_interval = defaults.progress_interval;
+  }
+  if settings.download_concurrency == 0 {
+    invalid.push(("download_concurrency".to_string(), "has to be at least 1".to_string()));
+    settings.download_concurrency = defaults.download_concurrency;
+  }
+  if ! ["ask", "1080p", "hdr", "smallest"].contains(&settings.version_preference.as_str()) {
+    invalid.push(("version_preference".to_string(), format!("unknown preference \"{}\"", settings.version_preference)));
+    settings.version_preference = defaults.version_preference;
+  }
+  if ! ["libmpv", "external", "vlc", "command"].contains(&settings.player_backend.as_str()) {
+    invalid.push(("player_backend".to_string(), format!("unknown player \"{}\"", settings.player_backend)));
+    settings.player_backend = defaults.player_backend;
+  }
+  (settings, invalid)
+}
+
+
+fn read_settings() -> Settings {
+  if ! Path::new(&settings_path()).is_file() {
     println!("No settings file found!\nBuilding default settings ...\n");
-    // Default <> server.
-    let server_config: Option<String> = search_server_configs();
-    // Discord Presence default setting.
-    let discord_presence: bool = initiate_discord();
-    // Activate encoded streaming (requires fully configured media-server).
-    let transcoding: bool = transcoding();
-    // Whether mpv should start in fullscreen mode.
-    let fullscreen: bool = start_fullscreen();
-    // Whether the user should be prompted if the default login is correct.
-    let autologin: bool = automatically_login();
-    // Whether the user should be prompted to continue after an episode has been finished.
-    let autoplay: bool = autoplay();
-    // Whether mpv should try to use hardware decoding.
-    let gpu: bool = gpu();
-
-    let settings = Settings {
-      server_config,
-      discord_presence,
-      transcoding,
-      fullscreen,
-      autologin,
-      autoplay,
-      gpu
-    };
-    let settings_file = toml::to_string_pretty(&settings).unwrap();
-    std::fs::write(config_path_string, settings_file).expect("Saving settings.");
-    settings
-  } else {
-    let settings_file_raw = Config::builder().add_source(File::from(Path::new(&config_path_string))).build().unwrap();
-    let serialized = settings_file_raw.try_deserialize::<Settings>();
-    match serialized {
-      Ok(settings) => {
-        settings
-      },
-      Err(e) => {
-        if e.to_string().contains("missing field") {
-          println!("{}", "Settings file is corrupt. Attempting to fix it ...".to_string().red());
-          let mut settings_file = fs::OpenOptions::new().write(true).append(true).open(&config_path_string).unwrap();
-          match &e.to_string()[e.to_string().find('`').unwrap() + 1..e.to_string().len() - 1] {
-            "server_config" => {
-              let server_config: Option<String> = search_server_configs();
-              write!(settings_file, "server_config = {server_config:?}").unwrap();
-              let settings = read_settings();
-              return settings;
-            },
-            "discord_presence" => {
-              let discord_presence: bool = initiate_discord();
-              write!(settings_file, "discord_presence = {discord_presence}").unwrap();
-              let settings = read_settings();
-              return settings;
-            },
-            "transcoding" => {
-              let transcoding: bool = transcoding();
-              write!(settings_file, "transcoding = {transcoding}").unwrap();
-              let settings = read_settings();
-              return settings;
-            },
-            "fullscreen" => {
-              let fullscreen: bool = start_fullscreen();
-              write!(settings_file, "fullscreen = {fullscreen}").unwrap();
-              let settings = read_settings();
-              return settings;
-            },
-            "autologin" => {
-              let autologin: bool = automatically_login();
-              write!(settings_file, "autologin = {autologin}").unwrap();
-              let settings = read_settings();
-              return settings;
-            },
-            "autoplay" => {
-              let autoplay: bool = autoplay();
-              write!(settings_file, "autoplay = {autoplay}").unwrap();
-              let settings = read_settings();
-              return settings;
-            },
-            "gpu" => {
-              let gpu: bool = gpu();
-              write!(settings_file, "gpu = {gpu}").unwrap();
-              let settings = read_settings();
-              return settings;
-            }
-            _ => {
-              println!("{}", "Failure.".to_string().red())
-            }
-          }
-        } else {
-          println!("{}", "Settings file is corrupt. Settings have to be reconfigured.\n".to_string().red());
-        }
-        let server_config: Option<String> = search_server_configs();
-        let discord_presence: bool = initiate_discord();
-        let transcoding: bool = transcoding();
-        let fullscreen: bool = start_fullscreen();
-        let autologin: bool = automatically_login();
-        let autoplay: bool = autoplay();
-        let gpu: bool = gpu();
-        let settings = Settings {
-          server_config,
-          discord_presence,
-          transcoding,
-          fullscreen,
-          autologin,
-          autoplay,
-          gpu
-        };
-        let settings_file = toml::to_string_pretty(&settings).unwrap();
-        std::fs::write(config_path_string, settings_file).expect("Saving settings.");
-        settings
+    let settings = initial_settings();
+    save_settings(&settings);
+    return settings
+  }
+  let raw: String = fs::read_to_string(settings_path()).expect("Reading settings failed.");
+  let table: Result<toml::Table, toml::de::Error> = raw.parse::<toml::Table>().or_else(|e| repair_unversioned(&raw).parse::<toml::Table>().map_err(|_| e));
+  let mut table: toml::Table = match table {
+    Ok(table) => table,
+    Err(e) => {
+      println!("{}\n{}", "Settings file is corrupt. Settings have to be reconfigured.".to_string().red(), e);
+      backup_settings("corrupt");
+      let settings = initial_settings();
+      save_settings(&settings);
+      return settings
+    }
+  };
+  let version: u32 = table.get("settings_version").and_then(|version| version.as_integer()).unwrap_or(0) as u32;
+  let migrated: bool = version < SETTINGS_VERSION;
+  if migrated {
+    backup_settings(&format!("v{version}"));
+    migrate(&mut table, version);
+  } else if version > SETTINGS_VERSION {
+    println!("{}", "These settings are from a newer version of puddler, unknown settings will be ignored.".to_string().yellow());
+  }
+  let (settings, invalid) = validate(table);
+  for (key, error) in &invalid {
+    println!("{}", format!("Invalid setting \"{key}\": {error}. Using the default value instead.").red());
+  }
+  if migrated || ! invalid.is_empty() {
+    if ! migrated {
+      backup_settings("invalid");
+    }
+    save_settings(&settings);
+  }
+  settings
+}
+
+
+// Settings, which don't need a value on the command-line.
+pub fn is_switch(key: &str) -> bool {
+  toml::Table::try_from(Settings::default()).is_ok_and(|table| table.get(key).is_some_and(|value| value.is_bool()))
+}
+
+
+// The value is tried as a switch, a number and a string, whatever the setting accepts.
+fn override_value(key: &str, raw: &str) -> Result<toml::Value, String> {
+  let candidates: Vec<toml::Value> = match raw.to_lowercase().as_str() {
+    "1" | "true" | "yes" | "on" if is_switch(key) => [toml::Value::Boolean(true)].to_vec(),
+    "0" | "false" | "no" | "off" if is_switch(key) => [toml::Value::Boolean(false)].to_vec(),
+    _ => [raw.parse::<i64>().ok().map(toml::Value::Integer), raw.parse::<f64>().ok().map(toml::Value::Float), Some(toml::Value::String(raw.to_string()))].into_iter().flatten().collect()
+  };
+  for value in candidates {
+    let single: toml::Table = [(key.to_string(), value.clone())].into_iter().collect();
+    if let Ok(settings) = toml::Value::Table(single).try_into::<Settings>() {
+      // Unknown settings are simply ignored while deserializing, so they would be missing here.
+      return match toml::Table::try_from(settings) {
+        Ok(table) if table.contains_key(key) => Ok(value),
+        _ => Err("there is no such setting".to_string())
       }
     }
   }
+  Err(format!("\"{raw}\" isn't a valid value"))
+}
+
+
+// Flags and environment variables only apply to this run, they are never saved.
+fn apply_arguments(settings: Settings) -> Settings {
+  let overrides = &arguments::get().overrides;
+  if overrides.is_empty() {
+    return settings
+  }
+  let mut table = toml::Table::try_from(&settings).unwrap();
+  for (key, raw) in overrides {
+    match override_value(key, raw) {
+      Ok(value) => {
+        table.insert(key.clone(), value);
+      },
+      Err(e) => println!("{}", format!("Ignoring the override of \"{key}\": {e}.").red())
+    }
+  }
+  let (settings, invalid) = validate(table);
+  for (key, error) in &invalid {
+    println!("{}", format!("Invalid setting \"{key}\": {error}. Using the default value instead.").red());
+  }
+  settings
 }
 
 
@@ -151,7 +421,9 @@ pub fn initialize_settings(mode: u8) -> Settings {
   let mut settings: Settings = read_settings();
   if mode == 1 {
     settings = change_settings(settings);
-  } else if mode == 2 {
+  }
+  settings = apply_arguments(settings);
+  if mode == 2 {
     display_settings(&settings);
   };
   settings
@@ -254,8 +526,6 @@ fn start_fullscreen() -> bool {
 
 
 fn change_settings(mut settings: Settings) -> Settings {
-  let config_path = get_app_root(AppDataType::UserConfig, &APP_INFO).unwrap();
-  let config_path_string = format!("{}/{}.toml", &config_path.display().to_string(), &APPNAME);
   loop {
     print!("Which settings do you want to change?
   [1] Default server configuration = {}
@@ -265,6 +535,24 @@ fn change_settings(mut settings: Settings) -> Settings {
   [5] Automatically login = {}
   [6] Autoplay = {}
   [7] Hardware decoding = {}
+  [8] Media version = {}
+  [9] Progress report interval = {}s
+  [0] Resume thresholds = {}
+  [D] Download folder = {}
+  [C] Concurrent downloads = {}
+  [B] Download speed limit = {}
+  [H] Playback hooks = {}
+  [P] Now-playing file = {}
+  [A] Artwork url = {}
+  [V] Player = {}
+  [M] mpv executable = {}
+  [T] Subtitle folder = {}
+  [L] VLC executable = {}
+  [O] Player command = {}
+  [E] Prefetched episodes = {}
+  [K] mpv config directory = {}
+  [G] Binge mode = {}
+  [R] Server specific settings
 \n  [S] Save and return to the menu",
 settings.server_config.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
 settings.discord_presence.to_string().green(),
@@ -272,9 +560,26 @@ settings.transcoding.to_string().green(),
 settings.fullscreen.to_string().green(),
 settings.autologin.to_string().green(),
 settings.autoplay.to_string().green(),
-settings.gpu.to_string().green()
+settings.gpu.to_string().green(),
+settings.version_preference.green(),
+settings.progress_interval.to_string().green(),
+format_resume_thresholds(&settings).green(),
+settings.download_folder.as_ref().unwrap_or(&"Default".to_string()).to_string().green(),
+settings.download_concurrency.to_string().green(),
+format_download_bandwidth(&settings).green(),
+format_hooks(&settings).green(),
+settings.presence_file.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+settings.presence_artwork_url.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+settings.player_backend.green(),
+settings.mpv_path.as_ref().unwrap_or(&"mpv".to_string()).to_string().green(),
+settings.subtitle_folder.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+settings.vlc_path.as_ref().unwrap_or(&"vlc".to_string()).to_string().green(),
+settings.player_command.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+settings.prefetch_episodes.to_string().green(),
+settings.mpv_config_dir.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+format_binge_mode(&settings).green()
     );
-    let menu = getch("1234567Ss");
+    let menu = getch("1234567890DdCcBbHhPpAaVvMmTtLlOoEeKkGgRrSs");
     match menu {
       '1' => {
         settings.server_config = search_server_configs();
@@ -297,6 +602,60 @@ settings.gpu.to_string().green()
       '7' => {
         settings.gpu = gpu();
       },
+      '8' => {
+        settings.version_preference = version_preference();
+      },
+      '9' => {
+        settings.progress_interval = progress_interval();
+      },
+      '0' => {
+        (settings.min_resume_pct, settings.max_resume_pct, settings.min_resume_duration_seconds) = resume_thresholds();
+      },
+      'D' | 'd' => {
+        settings.download_folder = download_folder();
+      },
+      'C' | 'c' => {
+        settings.download_concurrency = download_concurrency();
+      },
+      'B' | 'b' => {
+        settings.download_bandwidth = download_bandwidth();
+      },
+      'H' | 'h' => {
+        hooks(&mut settings.hooks);
+      },
+      'P' | 'p' => {
+        settings.presence_file = presence_file();
+      },
+      'A' | 'a' => {
+        settings.presence_artwork_url = presence_artwork_url();
+      },
+      'V' | 'v' => {
+        settings.player_backend = player_backend();
+      },
+      'M' | 'm' => {
+        settings.mpv_path = mpv_path();
+      },
+      'T' | 't' => {
+        settings.subtitle_folder = subtitle_folder();
+      },
+      'L' | 'l' => {
+        settings.vlc_path = vlc_path();
+      },
+      'O' | 'o' => {
+        settings.player_command = player_command();
+      },
+      'E' | 'e' => {
+        settings.prefetch_episodes = prefetch_episodes();
+      },
+      'K' | 'k' => {
+        settings.mpv_config_dir = mpv_config_dir();
+      },
+      'G' | 'g' => {
+        (settings.binge_countdown, settings.binge_episodes, settings.binge_hours) = binge_mode();
+      },
+      'R' | 'r' => {
+        server_overrides();
+      },
       'S' | 's' => {
         break
       },
@@ -304,8 +663,7 @@ settings.gpu.to_string().green()
       )
     };
   }
-  let settings_file = toml::to_string_pretty(&settings).unwrap();
-  std::fs::write(config_path_string, settings_file).expect("Saving settings failed.");
+  save_settings(&settings);
   settings
 }
 
@@ -318,6 +676,24 @@ fn display_settings(settings: &Settings) {
   Automatically login = {}
   Autoplay = {}
   Hardware decoding = {}
+  Media version = {}
+  Progress report interval = {}s
+  Resume thresholds = {}
+  Download folder = {}
+  Concurrent downloads = {}
+  Download speed limit = {}
+  Playback hooks = {}
+  Now-playing file = {}
+  Artwork url = {}
+  Player = {}
+  mpv executable = {}
+  Subtitle folder = {}
+  VLC executable = {}
+  Player command = {}
+  Prefetched episodes = {}
+  mpv config directory = {}
+  mpv options = {}
+  Binge mode = {}
 ",
   settings.server_config.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
   settings.discord_presence.to_string().green(),
@@ -325,11 +701,36 @@ fn display_settings(settings: &Settings) {
   settings.fullscreen.to_string().green(),
   settings.autologin.to_string().green(),
   settings.autoplay.to_string().green(),
-  settings.gpu.to_string().green()
+  settings.gpu.to_string().green(),
+  settings.version_preference.green(),
+  settings.progress_interval.to_string().green(),
+  format_resume_thresholds(settings).green(),
+  settings.download_folder.as_ref().unwrap_or(&"Default".to_string()).to_string().green(),
+  settings.download_concurrency.to_string().green(),
+  format_download_bandwidth(settings).green(),
+  format_hooks(settings).green(),
+  settings.presence_file.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+  settings.presence_artwork_url.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+  settings.player_backend.green(),
+  settings.mpv_path.as_ref().unwrap_or(&"mpv".to_string()).to_string().green(),
+  settings.subtitle_folder.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+  settings.vlc_path.as_ref().unwrap_or(&"vlc".to_string()).to_string().green(),
+  settings.player_command.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+  settings.prefetch_episodes.to_string().green(),
+  settings.mpv_config_dir.as_ref().unwrap_or(&"None".to_string()).to_string().green(),
+  format_mpv_options(settings).green(),
+  format_binge_mode(settings).green()
   );
 }
 
 
+// Options and profiles are only edited in the settings file.
+fn format_mpv_options(settings: &Settings) -> String {
+  let profiles: Vec<&String> = settings.mpv_profiles.keys().collect();
+  format!("{} option(s), profiles: {}", settings.mpv_options.len(), if profiles.is_empty() { "None".to_string() } else { profiles.iter().map(|name| name.to_string()).collect::<Vec<String>>().join(", ") })
+}
+
+
 fn automatically_login() -> bool {
   print!("Do you want to enable autologin on start?\n (Y)es / (N)o");
   let autologin = getch("YyNn");
@@ -371,3 +772,289 @@ fn gpu() -> bool {
     _ => false
   }
 }
+
+fn version_preference() -> String {
+  print!("Which version do you want to play, if an item has multiple versions?\n  [1] Always ask\n  [2] Prefer up to 1080p\n  [3] Prefer HDR\n  [4] Prefer the smallest file");
+  let preference = getch("1234");
+  match preference {
+    '2' => {
+      "1080p".to_string()
+    },
+    '3' => {
+      "hdr".to_string()
+    },
+    '4' => {
+      "smallest".to_string()
+    },
+    _ => "ask".to_string()
+  }
+}
+
+fn progress_interval() -> u64 {
+  print!("How often (in seconds) should the playback progress be sent to your server?\n(Pausing, seeking and track changes are always sent right away)\n: ");
+  let mut interval = String::new();
+  loop {
+    io::stdout().flush().expect("Failed to flush stdout");
+    interval.clear();
+    io::stdin().read_line(&mut interval).unwrap();
+    match interval.trim().parse::<u64>() {
+      Ok(seconds) if seconds > 0 => {
+        println!();
+        return seconds
+      },
+      _ => print!("\nInvalid input! Enter something like \"10\".\n: ")
+    }
+  }
+}
+
+fn resume_thresholds() -> (Option<f64>, Option<f64>, Option<u64>) {
+  fn take_input<T: std::str::FromStr>(question: &str) -> Option<T> {
+    print!("{question}\n(Leave empty to use the value of your server)\n: ");
+    let mut input = String::new();
+    loop {
+      io::stdout().flush().expect("Failed to flush stdout");
+      input.clear();
+      io::stdin().read_line(&mut input).unwrap();
+      if input.trim().is_empty() {
+        println!();
+        return None
+      }
+      match input.trim().parse::<T>() {
+        Ok(value) => {
+          println!();
+          return Some(value)
+        },
+        Err(_) => print!("\nInvalid input, please try again.\n: ")
+      }
+    }
+  }
+  (
+    take_input("Below which percentage shouldn't any progress be saved?"),
+    take_input("Above which percentage should an item be marked as played?"),
+    take_input("Items shorter than how many seconds can't be resumed (and will be marked as played)?")
+  )
+}
+
+fn format_resume_thresholds(settings: &Settings) -> String {
+  fn value<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or("server".to_string())
+  }
+  format!("min {}% / max {}% / min duration {}s", value(&settings.min_resume_pct), value(&settings.max_resume_pct), value(&settings.min_resume_duration_seconds))
+}
+
+fn download_folder() -> Option<String> {
+  print!("In which folder should downloaded items be stored?\n(Leave empty to use the default location)\n: ");
+  io::stdout().flush().expect("Failed to flush stdout");
+  let mut folder = String::new();
+  io::stdin().read_line(&mut folder).unwrap();
+  println!();
+  if folder.trim().is_empty() {
+    None
+  } else {
+    Some(folder.trim().to_string())
+  }
+}
+
+fn download_concurrency() -> u64 {
+  print!("How many items should be downloaded at the same time?\n: ");
+  let mut amount = String::new();
+  loop {
+    io::stdout().flush().expect("Failed to flush stdout");
+    amount.clear();
+    io::stdin().read_line(&mut amount).unwrap();
+    match amount.trim().parse::<u64>() {
+      Ok(amount) if amount > 0 => {
+        println!();
+        return amount
+      },
+      _ => print!("\nInvalid input! Enter something like \"2\".\n: ")
+    }
+  }
+}
+
+fn download_bandwidth() -> Option<u64> {
+  print!("How fast (in mbps) should all downloads together be at most?\n(Leave empty for no limit)\n: ");
+  let mut mbps = String::new();
+  loop {
+    io::stdout().flush().expect("Failed to flush stdout");
+    mbps.clear();
+    io::stdin().read_line(&mut mbps).unwrap();
+    if mbps.trim().is_empty() {
+      println!();
+      return None
+    }
+    match mbps.trim().parse::<u64>() {
+      Ok(mbps) if mbps > 0 => {
+        println!();
+        return Some(mbps)
+      },
+      _ => print!("\nInvalid input! Enter something like \"20\".\n: ")
+    }
+  }
+}
+
+fn format_download_bandwidth(settings: &Settings) -> String {
+  settings.download_bandwidth.map(|mbps| format!("{mbps} mbps")).unwrap_or("None".to_string())
+}
+
+fn hooks(hooks: &mut Hooks) {
+  println!("Hooks are either shell commands (item details are passed as PUDDLER_* environment variables)\nor http(s) urls, which receive the details as JSON.\n");
+  for event in HookEvent::ALL {
+    let hook = hooks.get_mut(event);
+    print!("Hook for \"{}\" (currently {}).\n(Leave empty to keep it, or type \"None\" to remove it)\n: ", event.name(), hook.as_ref().unwrap_or(&"None".to_string()).green());
+    io::stdout().flush().expect("Failed to flush stdout");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    match input.trim() {
+      "" => (),
+      "None" => *hook = None,
+      command => *hook = Some(command.to_string())
+    }
+    println!();
+  }
+}
+
+fn format_hooks(settings: &Settings) -> String {
+  let configured: Vec<&str> = HookEvent::ALL.iter().filter(|event| settings.hooks.get(**event).is_some()).map(|event| event.name()).collect();
+  if configured.is_empty() {
+    "None".to_string()
+  } else {
+    configured.join(", ")
+  }
+}
+
+fn optional_input() -> Option<String> {
+  io::stdout().flush().expect("Failed to flush stdout");
+  let mut input = String::new();
+  io::stdin().read_line(&mut input).unwrap();
+  println!();
+  if input.trim().is_empty() {
+    None
+  } else {
+    Some(input.trim().to_string())
+  }
+}
+
+fn presence_file() -> Option<String> {
+  print!("Which file should always contain the currently playing item?\n(Leave empty to disable it)\n: ");
+  optional_input()
+}
+
+fn presence_artwork_url() -> Option<String> {
+  print!("Under which public url is your server reachable? Discord needs it to show posters.\n(f.e. \"https://media.example.com\", leave empty to only show the server logo)\n: ");
+  optional_input()
+}
+
+fn player_backend() -> String {
+  print!("Which player should be used?\n  [1] Built-in (libmpv)\n  [2] External mpv (controlled through its JSON IPC)\n  [3] VLC (controlled through its HTTP interface)\n  [4] Custom command (the playback position can't be reported)");
+  match getch("1234") {
+    '2' => "external".to_string(),
+    '3' => "vlc".to_string(),
+    '4' => "command".to_string(),
+    _ => "libmpv".to_string()
+  }
+}
+
+fn mpv_path() -> Option<String> {
+  print!("Which mpv executable should be used for the external player and series?\n(Leave empty to use \"mpv\" from your PATH)\n: ");
+  optional_input()
+}
+
+fn subtitle_folder() -> Option<String> {
+  print!("Which folder should be searched for subtitles of series episodes?\n(Leave empty to not look for any)\n: ");
+  optional_input()
+}
+
+fn vlc_path() -> Option<String> {
+  print!("Which VLC executable should be used?\n(Leave empty to use \"vlc\" from your PATH)\n: ");
+  optional_input()
+}
+
+fn player_command() -> Option<String> {
+  print!("Which command should start your player?\n(Placeholders: {{url}} {{title}} {{start}} {{subfile}} {{header}}, f.e. \"celluloid {{url}} --mpv-http-header-fields={{header}}\")\n");
+  print!("Either way your access token is part of the player's command line (as {{header}} or in the url), where other users of this computer can see it in the process list.\n: ");
+  let command = optional_input();
+  if command.is_some() {
+    println!("{}", "Your access token will be part of the player's command line.".to_string().yellow());
+  }
+  command
+}
+
+fn mpv_config_dir() -> Option<String> {
+  print!("Which directory should mpv load its mpv.conf, input.conf and scripts from?\n(Leave empty to use mpv's defaults, the built-in player doesn't load any config then)\n: ");
+  optional_input()
+}
+
+fn binge_mode() -> (u64, u64, u64) {
+  fn take_input(question: &str) -> u64 {
+    print!("{question}\n: ");
+    let mut input = String::new();
+    loop {
+      io::stdout().flush().expect("Failed to flush stdout");
+      input.clear();
+      io::stdin().read_line(&mut input).unwrap();
+      match input.trim().parse::<u64>() {
+        Ok(value) => {
+          println!();
+          return value
+        },
+        Err(_) => print!("\nInvalid input, please try again.\n: ")
+      }
+    }
+  }
+  (
+    take_input("How many seconds should binge mode count down before the next episode starts?"),
+    take_input("After how many episodes without any interaction should you be asked whether you're still watching? (\"0\" disables it)"),
+    take_input("After how many hours without any interaction should you be asked whether you're still watching? (\"0\" disables it)")
+  )
+}
+
+fn format_binge_mode(settings: &Settings) -> String {
+  fn limit(value: u64, unit: &str) -> String {
+    if value == 0 { "never".to_string() } else { format!("{value} {unit}") }
+  }
+  format!("{}s countdown, still watching after {} / {}", settings.binge_countdown, limit(settings.binge_episodes, "episodes"), limit(settings.binge_hours, "hours"))
+}
+
+fn prefetch_episodes() -> u64 {
+  print!("How many of the next episodes should already be loaded in the background?\n(Every episode is a paused mpv instance, \"0\" disables it)\n: ");
+  let mut amount = String::new();
+  loop {
+    io::stdout().flush().expect("Failed to flush stdout");
+    amount.clear();
+    io::stdin().read_line(&mut amount).unwrap();
+    match amount.trim().parse::<u64>() {
+      Ok(amount) => {
+        println!();
+        return amount
+      },
+      _ => print!("\nInvalid input! Enter something like \"2\".\n: ")
+    }
+  }
+}
+
+fn server_overrides() {
+  print!("Which kind of server do you want to configure?\n   [1] Emby\n   [2] Jellyfin");
+  let server_kind = getch("12");
+  let Some(config_path) = choose_config(server_kind, false) else {
+    println!("{}", "There is no configuration for this kind of server yet.".to_string().red());
+    return
+  };
+  let mut overrides: SettingsOverrides = read_overrides(&config_path);
+  let fields: [(&str, &mut Option<bool>); 4] = [
+    ("Transcoding", &mut overrides.transcoding),
+    ("Hardware decoding", &mut overrides.gpu),
+    ("MPV fullscreen", &mut overrides.fullscreen),
+    ("Autoplay", &mut overrides.autoplay)
+  ];
+  for (name, value) in fields {
+    print!("{} on this server (currently {}):\n (Y)es / (N)o / (D)efault", name, value.map(|value| value.to_string()).unwrap_or("default".to_string()).green());
+    *value = match getch("YyNnDd") {
+      'Y' | 'y' => Some(true),
+      'N' | 'n' => Some(false),
+      _ => None
+    };
+  }
+  println!("Server specific settings: {}\n", overrides.to_string().green());
+  write_overrides(&config_path, overrides);
+}
\ No newline at end of file
@@ -0,0 +1,151 @@
+// Plays random episodes of a series or season, or random items of a library, without repeats within a session
+use std::io;
+use std::io::prelude::*;
+use std::sync::Mutex;
+use colored::Colorize;
+use isahc::ReadResponseExt;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use urlencoding::encode;
+use crate::binge::binge;
+use crate::mediaserver_information::{getch, HeadDict};
+use crate::settings::Settings;
+use crate::{print_menu, process_input, process_series, puddler_get, ItemJson, Items, SeriesStruct};
+
+
+// Ids of everything, which has already been shuffled and played since puddler has been started.
+static PLAYED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+
+#[derive(Debug, Deserialize)]
+struct Libraries {
+    Items: Vec<Library>
+}
+
+
+#[derive(Debug, Deserialize)]
+struct Library {
+    Name: String,
+    Id: String,
+    CollectionType: Option<String>
+}
+
+
+fn get<T: DeserializeOwned>(head_dict: &HeadDict, path: String) -> Option<T> {
+    let mut response = puddler_get(format!("{}{}{}", head_dict.config_file.ipaddress, head_dict.media_server, path), head_dict).ok()?;
+    serde_json::from_str(&response.text().ok()?).ok()
+}
+
+
+// Fisher-Yates, with the random numbers of uuid's v4 generator.
+fn shuffle(items: &mut [Items]) {
+    for index in (1..items.len()).rev() {
+        let other = (uuid::Uuid::new_v4().as_u128() % (index as u128 + 1)) as usize;
+        items.swap(index, other);
+    }
+}
+
+
+fn read_input(question: &str) -> String {
+    print!("{question}\n: ");
+    io::stdout().flush().expect("Failed to flush stdout");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    println!();
+    input.trim().to_string()
+}
+
+
+fn choose(amount: usize) -> usize {
+    loop {
+        match read_input("Please choose from above.").parse::<usize>() {
+            Ok(index) if index < amount => return index,
+            _ => println!("Invalid input, please try again.")
+        }
+    }
+}
+
+
+fn unwatched_only() -> bool {
+    print!("Should only unwatched items be played?\n (Y)es / (N)o");
+    "Yy".contains(getch("YyNn"))
+}
+
+
+fn play_shuffled(settings: &Settings, head_dict: &HeadDict, mut items: Vec<Items>, unwatched: bool) {
+    let played: Vec<String> = PLAYED.lock().unwrap().clone();
+    items.retain(|item| !(played.contains(&item.Id) || unwatched && item.UserData.Played));
+    if items.is_empty() {
+        println!("{}", "There is nothing left to shuffle.".to_string().yellow());
+        return
+    }
+    shuffle(&mut items);
+    println!("Shuffled {} item(s).\n", items.len());
+    binge(settings, head_dict, &items, 0, Some(&PLAYED));
+}
+
+
+// The episodes come from process_series, so specials are included just like in the normal episode list.
+pub fn shuffle_series(settings: &Settings, head_dict: &HeadDict, search: &str) {
+    let Some(results) = get::<ItemJson>(head_dict, format!("/Items?SearchTerm={}&UserId={}&Recursive=true&IncludeItemTypes=Series&Fields=PremiereDate,MediaSources", encode(search), head_dict.config_file.user_id)) else { return };
+    if results.Items.is_empty() {
+        println!("\nNo series found for: {}.", search.bold());
+        return
+    }
+    let series_list: Vec<Items> = print_menu(&results, false, Vec::new());
+    if series_list.len() > 1 {
+        print!(": ");
+        io::stdout().flush().expect("Failed to flush stdout");
+    }
+    let Some(pick) = process_input(&series_list, None) else { return };
+    let series: &Items = &series_list[pick as usize];
+    let Some(mut seasons) = get::<SeriesStruct>(head_dict, format!("/Users/{}/Items?ParentId={}&Fields=PremiereDate,MediaSources&collapseBoxSetItems=False", head_dict.config_file.user_id, series.Id)) else { return };
+    println!("  [A] All seasons");
+    for (index, season) in seasons.Items.iter().enumerate() {
+        println!("  [{index}] {}", season.Name);
+    }
+    let input = read_input("Which season should be shuffled?");
+    if let Some(index) = input.parse::<usize>().ok().filter(|index| *index < seasons.Items.len()) {
+        seasons.Items = [seasons.Items[index].clone()].to_vec();
+    } else if !"Aa".contains(&input) {
+        println!("Invalid input, shuffling all seasons.");
+    }
+    let unwatched = unwatched_only();
+    let episodes: Vec<Items> = process_series(&seasons, head_dict, false);
+    play_shuffled(settings, head_dict, episodes, unwatched);
+}
+
+
+// Movies or episodes of a library, the server already picks them at random.
+pub fn shuffle_library(settings: &Settings, head_dict: &HeadDict) {
+    let Some(libraries) = get::<Libraries>(head_dict, format!("/Users/{}/Views", head_dict.config_file.user_id)) else { return };
+    let libraries: Vec<Library> = libraries.Items.into_iter().filter(|library| matches!(library.CollectionType.as_deref(), Some("movies") | Some("tvshows"))).collect();
+    if libraries.is_empty() {
+        println!("{}", "There are no movie or series libraries.".to_string().yellow());
+        return
+    }
+    for (index, library) in libraries.iter().enumerate() {
+        println!("  [{index}] {}", library.Name);
+    }
+    let library: &Library = &libraries[choose(libraries.len())];
+    let item_type = if library.CollectionType.as_deref() == Some("movies") { "Movie" } else { "Episode" };
+    let mut filters = String::new();
+    let genres = read_input("Which genres should be included? (f.e. \"Comedy,Drama\", leave empty for all)");
+    if !genres.is_empty() {
+        filters.push_str(&format!("&Genres={}", encode(&genres.replace(',', "|"))));
+    }
+    let years = read_input("From which years? (f.e. \"2004\" or \"1990-1999\", leave empty for all)");
+    let years: Vec<String> = match years.split_once('-').map(|(from, to)| (from.trim().parse::<u32>(), to.trim().parse::<u32>())) {
+        Some((Ok(from), Ok(to))) => (from..=to).map(|year| year.to_string()).collect(),
+        _ => years.parse::<u32>().map(|year| [year.to_string()].to_vec()).unwrap_or_default()
+    };
+    if !years.is_empty() {
+        filters.push_str(&format!("&Years={}", years.join(",")));
+    }
+    let unwatched = unwatched_only();
+    if unwatched {
+        filters.push_str("&Filters=IsUnplayed");
+    }
+    let Some(items) = get::<ItemJson>(head_dict, format!("/Users/{}/Items?ParentId={}&Recursive=true&IncludeItemTypes={}&SortBy=Random&Limit=100&Fields=PremiereDate,MediaSources{}", head_dict.config_file.user_id, library.Id, item_type, filters)) else { return };
+    play_shuffled(settings, head_dict, items.Items, unwatched);
+}
@@ -0,0 +1,254 @@
+// Summarizes the local watch history together with the play counts of the server
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use app_dirs::*;
+use colored::Colorize;
+use isahc::ReadResponseExt;
+use rusqlite::{params, Connection};
+use serde_derive::{Deserialize, Serialize};
+use crate::mediaserver_information::{getch, HeadDict};
+use crate::{history, puddler_get, ItemJson, APP_INFO};
+
+
+#[derive(Debug, Serialize)]
+struct Table {
+    title: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>
+}
+
+
+#[derive(Debug, Deserialize)]
+struct GenreItem {
+    Id: String,
+    #[serde(default)]
+    Genres: Vec<String>
+}
+
+
+#[derive(Debug, Deserialize)]
+struct GenreJson {
+    Items: Vec<GenreItem>
+}
+
+
+// Seconds actually spent watching, seeking backwards doesn't count negatively.
+const WATCHED: &str = "MAX(last_position - start_position, 0)";
+
+
+fn hours(seconds: f64) -> String {
+    format!("{:.1}h", seconds / 3600.0)
+}
+
+
+fn table(title: &str, columns: &[&str], rows: Vec<Vec<String>>) -> Table {
+    Table {
+        title: title.to_string(),
+        columns: columns.iter().map(|column| column.to_string()).collect(),
+        rows
+    }
+}
+
+
+fn query(connection: &Connection, head_dict: &HeadDict, sql: &str, width: usize) -> Vec<Vec<String>> {
+    let mut statement = connection.prepare(sql).unwrap();
+    let rows = statement.query_map(params![head_dict.config_file.ipaddress, head_dict.config_file.user_id], |row| {
+        let mut columns: Vec<String> = Vec::new();
+        for index in 0..width {
+            let value: rusqlite::types::Value = row.get(index)?;
+            columns.push(match value {
+                rusqlite::types::Value::Integer(number) => number.to_string(),
+                rusqlite::types::Value::Real(number) => number.to_string(),
+                rusqlite::types::Value::Text(text) => text,
+                _ => "".to_string()
+            });
+        }
+        Ok(columns)
+    }).unwrap();
+    rows.flatten().collect()
+}
+
+
+fn watch_time(connection: &Connection, head_dict: &HeadDict, title: &str, format: &str, limit: usize) -> Table {
+    let rows = query(connection, head_dict, &format!("SELECT strftime('{format}', started_at, 'unixepoch', 'localtime') AS period, SUM({WATCHED}), COUNT(*)
+        FROM sessions WHERE server = ?1 AND user_id = ?2 GROUP BY period ORDER BY period DESC LIMIT {limit}"), 3);
+    table(title, &["Period", "Watched", "Sessions"], rows.into_iter()
+        .map(|row| [row[0].clone(), hours(row[1].parse().unwrap_or(0.0)), row[2].clone()].to_vec())
+        .collect())
+}
+
+
+// Genres aren't part of the history, so they're asked from the server for every movie and series.
+fn genres(head_dict: &HeadDict, ids: &[String]) -> HashMap<String, Vec<String>> {
+    let mut genres: HashMap<String, Vec<String>> = HashMap::new();
+    for chunk in ids.chunks(50) {
+        let response = puddler_get(format!("{}{}/Users/{}/Items?Ids={}&Fields=Genres",
+            head_dict.config_file.ipaddress, head_dict.media_server, head_dict.config_file.user_id, chunk.join(",")), head_dict);
+        if let Ok(mut response) = response {
+            if let Ok(items) = response.json::<GenreJson>() {
+                for item in items.Items {
+                    genres.insert(item.Id, item.Genres);
+                }
+            }
+        }
+    }
+    genres
+}
+
+
+fn per_genre(connection: &Connection, head_dict: &HeadDict) -> Table {
+    let watched: Vec<(String, f64)> = query(connection, head_dict, &format!("SELECT COALESCE(series_id, item_id), SUM({WATCHED})
+        FROM sessions WHERE server = ?1 AND user_id = ?2 GROUP BY COALESCE(series_id, item_id)"), 2)
+        .into_iter()
+        .map(|row| (row[0].clone(), row[1].parse().unwrap_or(0.0)))
+        .collect();
+    let mut per_genre: HashMap<String, f64> = HashMap::new();
+    if !head_dict.offline {
+        let ids: Vec<String> = watched.iter().map(|(id, _)| id.clone()).collect();
+        let genres = genres(head_dict, &ids);
+        for (id, seconds) in &watched {
+            for genre in genres.get(id).into_iter().flatten() {
+                *per_genre.entry(genre.clone()).or_insert(0.0) += seconds;
+            }
+        }
+    }
+    let mut rows: Vec<(String, f64)> = per_genre.into_iter().collect();
+    rows.sort_by(|a, b| b.1.total_cmp(&a.1));
+    table("Watch time per genre", &["Genre", "Watched"], rows.into_iter().take(10)
+        .map(|(genre, seconds)| [genre, hours(seconds)].to_vec())
+        .collect())
+}
+
+
+fn most_rewatched(head_dict: &HeadDict) -> Table {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    if !head_dict.offline {
+        let response = puddler_get(format!("{}{}/Users/{}/Items?Recursive=true&IncludeItemTypes=Movie,Episode&Filters=IsPlayed&SortBy=PlayCount,DatePlayed&SortOrder=Descending&Limit=10",
+            head_dict.config_file.ipaddress, head_dict.media_server, head_dict.config_file.user_id), head_dict);
+        if let Ok(mut response) = response {
+            if let Ok(items) = response.json::<ItemJson>() {
+                for item in items.Items {
+                    let name = match &item.SeriesName {
+                        Some(series) => format!("{} - {}", series, item.Name),
+                        None => item.Name.clone()
+                    };
+                    rows.push([
+                        name,
+                        item.UserData.PlayCount.unwrap_or(0).to_string(),
+                        item.UserData.LastPlayedDate.as_ref().map(|date| date.chars().take(10).collect()).unwrap_or("????".to_string())
+                    ].to_vec());
+                }
+            }
+        }
+    }
+    table("Most rewatched", &["Item", "Plays", "Last played"], rows)
+}
+
+
+fn report(connection: &Connection, head_dict: &HeadDict) -> Vec<Table> {
+    let mut tables: Vec<Table> = Vec::new();
+    tables.push(watch_time(connection, head_dict, "Watch time per day", "%Y-%m-%d", 7));
+    tables.push(watch_time(connection, head_dict, "Watch time per week", "%Y-W%W", 4));
+    tables.push(watch_time(connection, head_dict, "Watch time per month", "%Y-%m", 6));
+    let series = query(connection, head_dict, &format!("SELECT series_name, SUM({WATCHED}), COUNT(DISTINCT item_id)
+        FROM sessions WHERE server = ?1 AND user_id = ?2 AND series_name IS NOT NULL GROUP BY series_name ORDER BY SUM({WATCHED}) DESC LIMIT 10"), 3);
+    tables.push(table("Watch time per series", &["Series", "Watched", "Episodes"], series.into_iter()
+        .map(|row| [row[0].clone(), hours(row[1].parse().unwrap_or(0.0)), row[2].clone()].to_vec())
+        .collect()));
+    tables.push(per_genre(connection, head_dict));
+    let overall = query(connection, head_dict, &format!("SELECT COUNT(*), SUM(played), COUNT(DISTINCT item_id), COUNT(DISTINCT CASE WHEN played THEN item_id END), AVG({WATCHED})
+        FROM sessions WHERE server = ?1 AND user_id = ?2"), 5);
+    if let Some(row) = overall.first() {
+        let number = |index: usize| row[index].parse::<f64>().unwrap_or(0.0);
+        let rate = |part: f64, total: f64| if total > 0.0 { format!("{:.0}%", part / total * 100.0) } else { "-".to_string() };
+        tables.push(table("Overall", &["Statistic", "Value"], [
+            ["Sessions".to_string(), row[0].clone()].to_vec(),
+            ["Sessions finished".to_string(), rate(number(1), number(0))].to_vec(),
+            ["Items started".to_string(), row[2].clone()].to_vec(),
+            ["Items finished".to_string(), rate(number(3), number(2))].to_vec(),
+            ["Average session".to_string(), format!("{:.0}min", number(4) / 60.0)].to_vec()
+        ].to_vec()));
+    }
+    tables.push(most_rewatched(head_dict));
+    tables
+}
+
+
+fn print_table(table: &Table) {
+    println!("\n{}:", table.title.cyan());
+    if table.rows.is_empty() {
+        println!("      Nothing recorded yet.");
+        return
+    }
+    let mut widths: Vec<usize> = table.columns.iter().map(|column| column.chars().count()).collect();
+    for row in &table.rows {
+        for (index, value) in row.iter().enumerate() {
+            widths[index] = widths[index].max(value.chars().count()).min(50);
+        }
+    }
+    let line = |values: &Vec<String>| values.iter().enumerate()
+        .map(|(index, value)| format!("{:<width$}", value.chars().take(50).collect::<String>(), width = widths[index]))
+        .collect::<Vec<String>>().join("  ");
+    println!("      {}", line(&table.columns).bold());
+    for row in &table.rows {
+        println!("      {}", line(row));
+    }
+}
+
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+
+fn export(tables: &Vec<Table>, json: bool) -> Result<PathBuf, String> {
+    let folder = get_app_root(AppDataType::UserData, &APP_INFO).map_err(|e| e.to_string())?;
+    fs::create_dir_all(&folder).map_err(|e| e.to_string())?;
+    let (file, content) = if json {
+        (folder.join("stats.json"), serde_json::to_string_pretty(tables).unwrap())
+    } else {
+        // Every table has its own columns, so each one gets a section with its title and header row, separated by an empty line.
+        let csv_line = |values: &Vec<String>| values.iter().map(|value| csv_field(value)).collect::<Vec<String>>().join(",") + "\n";
+        let sections: Vec<String> = tables.iter().map(|table| {
+            let mut section = csv_field(&table.title) + "\n";
+            section.push_str(&csv_line(&table.columns));
+            for row in &table.rows {
+                section.push_str(&csv_line(row));
+            }
+            section
+        }).collect();
+        let content = sections.join("\n");
+        (folder.join("stats.csv"), content)
+    };
+    fs::write(&file, content).map_err(|e| e.to_string())?;
+    Ok(file)
+}
+
+
+pub fn print_stats(head_dict: &HeadDict) {
+    let tables = match history::connection() {
+        Some(connection) => report(&connection, head_dict),
+        None => {
+            println!("{}", "Couldn't open the watch history.".to_string().red());
+            return
+        }
+    };
+    for table in &tables {
+        print_table(table);
+    }
+    print!("\nDo you want to export these statistics?\n  (C)SV | (J)SON | (N)o");
+    let json = match getch("CcJjNn") {
+        'C' | 'c' => false,
+        'J' | 'j' => true,
+        _ => return
+    };
+    match export(&tables, json) {
+        Ok(file) => println!("{}", format!("Saved the statistics to \"{}\".", file.display()).green()),
+        Err(e) => println!("{}\n  Error: {}", "Couldn't export the statistics!".to_string().red(), e)
+    }
+}